@@ -1,2 +1,2 @@
 cargo build --target aarch64-unknown-linux-gnu --release
-scp target/aarch64-unknown-linux-gnu/release/light_sampler Matthew@matthew-y-ai:/home/Matthew/bin
+scp ../target/aarch64-unknown-linux-gnu/release/light_sampler Matthew@matthew-y-ai:/home/Matthew/bin
@@ -1,3 +1 @@
-pub mod encoder;
-pub mod mcp320x;
 pub mod pwm;
@@ -1,7 +1,8 @@
 mod hal;
 mod sampler;
 
-use hal::{encoder, mcp320x, pwm};
+use hal::pwm;
+use hat_hal::{adc, encoder, mcp3x0x};
 use sampler::Sampler;
 use std::io;
 use std::net;
@@ -50,11 +51,31 @@ fn main() -> anyhow::Result<()> {
     socket.set_nonblocking(true)?;
     let mut sampler = Sampler::new();
     let mut led = pwm::Pwm::new(PWM_PATH);
-    let mut encoder = encoder::Encoder::new(0, 600, 10)?;
-
-    let adc = mcp320x::MCP320X::new(ADC_PATH, 3.3)?;
-    let (sample_thread, sample_rx, sample_kill_tx) =
-        adc.make_sample_thread(mcp320x::Channel::CH0, 10);
+    let encoder_pins = gpiod::Chip::new("gpiochip0")?.request_lines(
+        gpiod::Options::input([7, 10]) // [GPIO 23, GPIO 24]
+            .active(gpiod::Active::High)
+            .bias(gpiod::Bias::PullDown)
+            .edge(gpiod::EdgeDetect::Both),
+    )?;
+    let mut encoder = encoder::Encoder::new(encoder_pins)?;
+    // Fast spins sweep the frequency range in a few turns, slow turns still step by 1 Hz.
+    encoder.set_acceleration(encoder::Acceleration {
+        threshold: 4.0,
+        gain: 1.0,
+        exponent: 1.5,
+        max: 40.0,
+    });
+    let mut freq_offset = 10;
+
+    let adc = mcp3x0x::Mcp3x0x::new(ADC_PATH, mcp3x0x::Family::MCP320X, 3.3)?;
+    let (sample_thread, sample_rx, sample_kill_tx) = adc::make_scan_thread(
+        adc,
+        vec![adc::ScanGroup {
+            inputs: vec![adc::Channel::CH0.into()],
+            period: time::Duration::from_millis(1),
+            sample_count: 10,
+        }],
+    );
 
     led.init()?;
 
@@ -63,10 +84,12 @@ fn main() -> anyhow::Result<()> {
     loop {
         let now = time::Instant::now();
 
-        let pwm_freq = pwm::Frequency::hz(encoder.get_offset() as u64);
+        encoder.update()?;
+        freq_offset = (freq_offset + encoder.get_acc_delta()).clamp(0, 600);
+        let pwm_freq = pwm::Frequency::hz(freq_offset as u64);
         led.set(pwm_freq)?;
 
-        sampler.extend_samples(sample_rx.try_iter(), now);
+        sampler.extend_samples(sample_rx.try_iter().map(sampler::Sample::from), now);
 
         if last_report.is_none_or(|last_report| now - last_report > REPORT_PERIOD) {
             log_current(&sampler, pwm_freq, now);
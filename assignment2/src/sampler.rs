@@ -3,6 +3,8 @@
  */
 use std::{fmt::Display, time};
 
+use hat_hal::adc::Reading;
+
 /// A single sensor sample in time
 #[derive(Debug, Clone, Copy)]
 pub struct Sample {
@@ -16,6 +18,13 @@ impl Sample {
     }
 }
 
+impl From<Reading> for Sample {
+    /// Take the first channel of a scan.
+    fn from(reading: Reading) -> Self {
+        Self::new(reading.voltages().next().unwrap_or_default(), reading.time)
+    }
+}
+
 /// Container for samples
 #[derive(Debug)]
 pub struct Sampler {
@@ -39,6 +39,33 @@ function handleCommand(socket) {
 		console.log("Got play command: " + songNumber);
 		relayToLocalPort(socket, "play " + songNumber, "play-reply");
 	});
+	socket.on('song', function(arrangement) {
+		console.log("Got song command: " + arrangement);
+		relayToLocalPort(socket, "song " + arrangement, "song-reply");
+	});
+	socket.on('toggle', function(trackAndStep) {
+		relayToLocalPort(socket, "toggle " + trackAndStep, "toggle-reply");
+	});
+	socket.on('clear', function(track) {
+		relayToLocalPort(socket, "clear " + track, "clear-reply");
+	});
+	socket.on('length', function(bars) {
+		relayToLocalPort(socket, "length " + bars, "length-reply");
+	});
+	socket.on('grid', function(notUsed) {
+		relayToLocalPort(socket, "grid", "grid-reply");
+	});
+	socket.on('save', function(name) {
+		console.log("Got save command: " + name);
+		relayToLocalPort(socket, "save " + name, "save-reply");
+	});
+	socket.on('load', function(name) {
+		console.log("Got load command: " + name);
+		relayToLocalPort(socket, "load " + name, "load-reply");
+	});
+	socket.on('position', function(notUsed) {
+		relayToLocalPort(socket, "position", "position-reply");
+	});
 	socket.on('stop', function(notUsed) {
 		console.log("Got stop command: ");
 		relayToLocalPort(socket, "stop", "stop-reply");
@@ -14,6 +14,8 @@ $(document).ready(function () {
     window.setInterval(function () { sendCommandToServer('volume') }, 1000);
     window.setInterval(function () { sendCommandToServer('mode') }, 1000);
     window.setInterval(function () { sendCommandToServer('tempo') }, 1000);
+    window.setInterval(function () { sendCommandToServer('position') }, 250);
+    window.setInterval(function () { sendCommandToServer('grid') }, 1000);
 
     // Start off by "polling" the volume, mode, and tempo:
     sendCommandToServer('volume');
@@ -31,6 +33,32 @@ $(document).ready(function () {
     $('#modeFunky').click(function () {
         sendCommandToServer('mode', "2");
     });
+    $('#modeFive').click(function () {
+        sendCommandToServer('mode', "3");
+    });
+
+    $('#grid').on('click', '.step', function () {
+        sendCommandToServer('toggle', $(this).data('track') + " " + $(this).data('step'));
+        sendCommandToServer('grid');
+    });
+    $('#grid').on('click', '.clear', function () {
+        sendCommandToServer('clear', $(this).data('track'));
+        sendCommandToServer('grid');
+    });
+    $('#lengthSet').click(function () {
+        sendCommandToServer('length', $('#lengthid').val());
+        sendCommandToServer('grid');
+    });
+    $('#patternSave').click(function () {
+        sendCommandToServer('save', $('#patternid').val());
+    });
+    $('#patternLoad').click(function () {
+        sendCommandToServer('load', $('#patternid').val());
+    });
+
+    $('#songPlay').click(function () {
+        sendCommandToServer('song', $('#songid').val());
+    });
 
     $('#volumeUp').click(function () {
         volume += 5;
@@ -102,6 +130,7 @@ function setupServerMessageHandlers(socket) {
             case 0: name = "None"; break;
             case 1: name = "Standard"; break;
             case 2: name = "Funky"; break;
+            case 3: name = "Five (5/4)"; break;
         }
         $('#modeid').text(name);
         clearServerTimeout();
@@ -121,6 +150,59 @@ function setupServerMessageHandlers(socket) {
         clearServerTimeout();
     });
 
+    socket.on('song-reply', function (message) {
+        console.log("Receive Reply: song-reply " + message);
+        $('#songstatus').text(message);
+        clearServerTimeout();
+    });
+
+    socket.on('grid-reply', function (message) {
+        var names = ["Hi-Hat", "Snare", "Base"];
+        var html = "";
+        message.split("\n").forEach(function (line) {
+            var parts = line.split(" ");
+            var track = parts[0];
+            html += "<div>" + names[track] + " ";
+            parts[1].split("").forEach(function (cell, step) {
+                html += '<input type="button" class="step" data-track="' + track + '" data-step="' + step
+                    + '" value="' + (cell == 'x' ? "X" : "-") + '" />';
+            });
+            html += ' <input type="button" class="clear" data-track="' + track + '" value="Clear" /></div>';
+        });
+        $('#grid').html(html);
+        clearServerTimeout();
+    });
+
+    socket.on('toggle-reply', function (message) {
+        clearServerTimeout();
+    });
+
+    socket.on('clear-reply', function (message) {
+        clearServerTimeout();
+    });
+
+    socket.on('length-reply', function (message) {
+        $('#lengthid').val(message);
+        clearServerTimeout();
+    });
+
+    socket.on('save-reply', function (message) {
+        console.log("Receive Reply: save-reply " + message);
+        $('#patternstatus').text(message);
+        clearServerTimeout();
+    });
+
+    socket.on('load-reply', function (message) {
+        console.log("Receive Reply: load-reply " + message);
+        $('#patternstatus').text(message);
+        clearServerTimeout();
+    });
+
+    socket.on('position-reply', function (message) {
+        $('#positionid').text(message);
+        clearServerTimeout();
+    });
+
     socket.on('play-reply', function (message) {
         console.log("Receive Reply: play-reply " + message);
         clearServerTimeout();
@@ -2,8 +2,12 @@ use std::fmt;
 use std::num::ParseIntError;
 use std::str::FromStr;
 
+use crate::input::mapping::{Action, Control};
 use crate::sound::Instrument;
+use crate::sound::effects::Bus;
+use crate::sound::looper::LoopMode;
 use crate::sound::score::ScoreType;
+use crate::sound::song::Song;
 use crate::units::{Bpm, Volume};
 
 #[derive(Debug, Clone, PartialEq)]
@@ -12,9 +16,64 @@ pub enum Command {
     Volume(Option<Volume>),
     Tempo(Option<Bpm>),
     Play(Option<Instrument>),
+    Position,
+    Song(Option<Song>),
+    Toggle(Option<(Instrument, usize)>),
+    Clear(Option<Instrument>),
+    Length(Option<u32>),
+    Grid(Option<Instrument>),
+    Save(Option<String>),
+    Load(Option<String>),
+    /// Map an input to an action, or to nothing with `None`.
+    Map(Option<(Control, Option<Action>)>),
+    /// Set an effect parameter, as (bus, effect, parameter, value).
+    Fx(Option<(Bus, String, String, f64)>),
+    /// Set how much of an instrument goes to the send bus, in percent.
+    Send(Option<(Instrument, u32)>),
+    /// Tune an instrument, as (instrument, semitones, cents).
+    Tune(Option<(Instrument, i32, i32)>),
+    /// Control the backing loop.
+    Loop(Option<LoopCommand>),
     Stop,
 }
 
+/// What to do with the backing loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopCommand {
+    /// Start from the top on the next bar line.
+    Start,
+    Stop,
+    Mute,
+    Unmute,
+    Mode(LoopMode),
+}
+
+impl fmt::Display for LoopCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoopCommand::Start => write!(f, "start"),
+            LoopCommand::Stop => write!(f, "stop"),
+            LoopCommand::Mute => write!(f, "mute"),
+            LoopCommand::Unmute => write!(f, "unmute"),
+            LoopCommand::Mode(mode) => write!(f, "{}", mode),
+        }
+    }
+}
+
+impl FromStr for LoopCommand {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(LoopCommand::Start),
+            "stop" => Ok(LoopCommand::Stop),
+            "mute" => Ok(LoopCommand::Mute),
+            "unmute" => Ok(LoopCommand::Unmute),
+            mode => mode.parse().map(LoopCommand::Mode),
+        }
+    }
+}
+
 pub enum Error {
     Empty,
     Invalid(String),
@@ -50,6 +109,76 @@ impl fmt::Display for Command {
                 n.map(|v| v.to_index().to_string())
                     .unwrap_or("null".to_owned())
             ),
+            Command::Position => write!(f, "position"),
+            Command::Song(song) => write!(
+                f,
+                "song {}",
+                song.as_ref()
+                    .map(Song::to_string)
+                    .unwrap_or("null".to_owned())
+            ),
+            Command::Toggle(n) => write!(
+                f,
+                "toggle {}",
+                n.map(|(i, step)| format!("{} {}", i.to_index(), step))
+                    .unwrap_or("null".to_owned())
+            ),
+            Command::Clear(n) => write!(
+                f,
+                "clear {}",
+                n.map(|v| v.to_index().to_string())
+                    .unwrap_or("null".to_owned())
+            ),
+            Command::Length(n) => write!(
+                f,
+                "length {}",
+                n.map(|v| v.to_string()).unwrap_or("null".to_owned())
+            ),
+            Command::Grid(n) => write!(
+                f,
+                "grid {}",
+                n.map(|v| v.to_index().to_string())
+                    .unwrap_or("null".to_owned())
+            ),
+            Command::Save(n) => write!(f, "save {}", n.as_deref().unwrap_or("null")),
+            Command::Load(n) => write!(f, "load {}", n.as_deref().unwrap_or("null")),
+            Command::Map(n) => write!(
+                f,
+                "map {}",
+                n.map(|(control, action)| format!(
+                    "{} {}",
+                    control,
+                    action.map(|a| a.to_string()).unwrap_or("none".to_owned())
+                ))
+                .unwrap_or("null".to_owned())
+            ),
+            Command::Fx(n) => write!(
+                f,
+                "fx {}",
+                n.as_ref()
+                    .map(|(bus, effect, param, value)| format!(
+                        "{} {} {} {}",
+                        bus, effect, param, value
+                    ))
+                    .unwrap_or("null".to_owned())
+            ),
+            Command::Send(n) => write!(
+                f,
+                "send {}",
+                n.map(|(i, level)| format!("{} {}", i.to_index(), level))
+                    .unwrap_or("null".to_owned())
+            ),
+            Command::Tune(n) => write!(
+                f,
+                "tune {}",
+                n.map(|(i, semitones, cents)| format!("{} {} {}", i.to_index(), semitones, cents))
+                    .unwrap_or("null".to_owned())
+            ),
+            Command::Loop(n) => write!(
+                f,
+                "loop {}",
+                n.map(|v| v.to_string()).unwrap_or("null".to_owned())
+            ),
             Command::Stop => write!(f, "stop"),
         }
     }
@@ -65,6 +194,19 @@ impl FromStr for Command {
     /// - "volume 50"
     /// - "tempo 120"
     /// - "play 2"
+    /// - "position"
+    /// - "song 1x4+2 2x4 stop"
+    /// - "toggle 0 3" (instrument, step)
+    /// - "clear 1"
+    /// - "length 2" (bars)
+    /// - "grid 0"
+    /// - "save groove"
+    /// - "load groove"
+    /// - "map encoder tempo 2" (input, action), or "map joystick-left none"
+    /// - "fx master filter cutoff 800" (bus, effect, parameter, value)
+    /// - "send 1 30" (instrument, percent)
+    /// - "tune 2 -3 50" (instrument, semitones, optional cents)
+    /// - "loop start", "loop stop", "loop mute", "loop unmute", "loop stretch" or "loop retrigger"
     /// - "stop"
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.trim();
@@ -80,7 +222,7 @@ impl FromStr for Command {
                 parts
                     .next()
                     .and_then(|p| p.parse().ok())
-                    .map(|n| ScoreType::from_index(n)),
+                    .map(ScoreType::from_index),
             )),
             "volume" => Ok(Command::Volume(
                 parts
@@ -98,10 +240,108 @@ impl FromStr for Command {
                 parts
                     .next()
                     .and_then(|p| p.parse().ok())
-                    .map(|n| Instrument::from_index(n)),
+                    .map(Instrument::from_index),
+            )),
+            "position" => Ok(Command::Position),
+            "song" => Ok(Command::Song(
+                parts.collect::<Vec<_>>().join(" ").parse().ok(),
+            )),
+            "toggle" => Ok(Command::Toggle(
+                parts
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .map(Instrument::from_index)
+                    .zip(parts.next().and_then(|p| p.parse().ok())),
+            )),
+            "clear" => Ok(Command::Clear(
+                parts
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .map(Instrument::from_index),
+            )),
+            "length" => Ok(Command::Length(
+                parts.next().and_then(|p| p.parse().ok()).filter(|&n| n > 0),
             )),
+            "grid" => Ok(Command::Grid(
+                parts
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .map(Instrument::from_index),
+            )),
+            "save" => Ok(Command::Save(parts.next().map(str::to_owned))),
+            "load" => Ok(Command::Load(parts.next().map(str::to_owned))),
+            "map" => Ok(Command::Map(parts.next().and_then(|control| {
+                let control = control.parse().ok()?;
+                match parts.collect::<Vec<_>>().join(" ").as_str() {
+                    "none" => Some((control, None)),
+                    action => Some((control, Some(action.parse().ok()?))),
+                }
+            }))),
+            "fx" => Ok(Command::Fx((|| {
+                let bus = parts.next()?.parse().ok()?;
+                let effect = parts.next()?.to_owned();
+                let param = parts.next()?.to_owned();
+                let value = parts.next()?.parse().ok().filter(|v: &f64| v.is_finite())?;
+                Some((bus, effect, param, value))
+            })())),
+            "send" => Ok(Command::Send(
+                parts
+                    .next()
+                    .and_then(|p| p.parse().ok())
+                    .map(Instrument::from_index)
+                    .zip(
+                        parts
+                            .next()
+                            .and_then(|p| p.parse().ok())
+                            .filter(|&n| n <= 100),
+                    ),
+            )),
+            "tune" => Ok(Command::Tune((|| {
+                let instrument = Instrument::from_index(parts.next()?.parse().ok()?);
+                let semitones = parts.next()?.parse().ok().filter(|n: &i32| n.abs() <= 24)?;
+                let cents = match parts.next() {
+                    Some(cents) => cents.parse().ok().filter(|n: &i32| n.abs() < 100)?,
+                    None => 0,
+                };
+                Some((instrument, semitones, cents))
+            })())),
+            "loop" => Ok(Command::Loop(parts.next().and_then(|p| p.parse().ok()))),
             "stop" => Ok(Command::Stop),
             other => Err(Error::Invalid(other.to_owned())),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(input: &str) -> String {
+        match input.parse::<Command>() {
+            Ok(command) => command.to_string(),
+            Err(_) => "!error".to_owned(),
+        }
+    }
+
+    #[test]
+    fn golden_round_trip() {
+        let golden = include_str!("../tests/golden/commands.txt");
+        let mut failures = Vec::new();
+        for line in golden.lines().filter(|line| !line.starts_with('#')) {
+            let (input, expected) = line.split_once("=>").expect("Golden lines must have a =>.");
+            let input = input.strip_suffix(' ').unwrap_or(input);
+            let expected = expected.trim();
+
+            let actual = display(input);
+            if actual != expected {
+                failures.push(format!(
+                    "{:?} => {:?}, expected {:?}",
+                    input, actual, expected
+                ));
+            } else if expected != "!error" && display(expected) != expected {
+                failures.push(format!("{:?} doesn't parse back to itself", expected));
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
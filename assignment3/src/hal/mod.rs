@@ -1,3 +0,0 @@
-pub mod button;
-pub mod encoder;
-pub mod mcp320x;
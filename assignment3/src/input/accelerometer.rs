@@ -1,16 +1,119 @@
-use crate::hal::mcp320x::{Channel, MCP320X};
+use std::{fmt::Write, fs, io, path::Path, time::Duration};
 
-type Acceleration = f64;
+use hat_hal::adc::{Adc, Channel, Input, Reading, ScanGroup};
 
-type Measurement = [Acceleration; 3];
+pub type Acceleration = f64;
+
+pub type Measurement = [Acceleration; 3];
+
+/// Voltages read from the X, Y and Z channels.
+pub type Voltages = [f64; 3];
+
+/// How one axis maps voltage to acceleration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisCalibration {
+    /// Voltage at 0 g.
+    pub offset: f64,
+    /// Change in voltage for 1 g. Negative if the axis is mounted reversed.
+    pub voltage_per_g: f64,
+}
+
+impl AxisCalibration {
+    /// A calibration, if the scale is large enough to divide by.
+    pub fn new(offset: f64, voltage_per_g: f64) -> Option<Self> {
+        (voltage_per_g.abs() > 0.05).then_some(Self {
+            offset,
+            voltage_per_g,
+        })
+    }
+
+    /// Find the offset and scale from readings with the axis pointing up (+1 g) and down (-1 g).
+    /// An axis that barely moves between the two is unplugged or wasn't turned over.
+    pub fn from_readings(up: f64, down: f64) -> Option<Self> {
+        Self::new((up + down) / 2.0, (up - down) / 2.0)
+    }
+
+    fn to_g(self, voltage: f64) -> Acceleration {
+        (voltage - self.offset) / self.voltage_per_g
+    }
+}
+
+/// Per-axis calibration, kept as a text file with one `<axis> <offset> <voltage_per_g>` line per axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub axes: [AxisCalibration; 3],
+}
+
+impl Calibration {
+    const NAMES: [&str; 3] = ["x", "y", "z"];
+
+    /// The same center and sensitivity on every axis, from the datasheet.
+    pub fn uniform(center: f64, voltage_per_g: f64) -> Self {
+        Self {
+            axes: [AxisCalibration {
+                offset: center,
+                voltage_per_g,
+            }; 3],
+        }
+    }
+
+    /// Calibrate from the six orientations, as (up, down) voltages for each axis.
+    pub fn from_orientations(readings: [(Voltages, Voltages); 3]) -> Option<Self> {
+        let [x, y, z] = [0, 1, 2].map(|i| {
+            let (up, down) = readings[i];
+            AxisCalibration::from_readings(up[i], down[i])
+        });
+        Some(Self { axes: [x?, y?, z?] })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).ok_or(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Calibration file must have an x, y and z line.",
+        ))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut text = String::new();
+        for (name, axis) in Self::NAMES.iter().zip(self.axes) {
+            writeln!(text, "{} {} {}", name, axis.offset, axis.voltage_per_g).unwrap();
+        }
+        fs::write(path, text)
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut axes = [None; 3];
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            let Some(name) = parts.next() else {
+                continue;
+            };
+            let i = Self::NAMES.iter().position(|n| *n == name)?;
+            axes[i] = Some(AxisCalibration::new(
+                parts.next()?.parse().ok()?,
+                parts.next()?.parse().ok()?,
+            )?);
+        }
+        match axes {
+            [Some(x), Some(y), Some(z)] => Some(Self { axes: [x, y, z] }),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Calibration {
+    /// Nominal values for the board's accelerometer, before calibrating.
+    fn default() -> Self {
+        Self::uniform(1.57, 0.42)
+    }
+}
 
 pub struct Accelerometer {
     x_axis: Channel,
     y_axis: Channel,
     z_axis: Channel,
 
-    center: f64,
-    voltage_per_g: f64,
+    calibration: Calibration,
 
     sample_count: usize,
 }
@@ -20,26 +123,39 @@ impl Accelerometer {
         x_axis: Channel,
         y_axis: Channel,
         z_axis: Channel,
-        center: f64,
-        voltage_per_g: f64,
+        calibration: Calibration,
     ) -> Self {
         Self {
             x_axis,
             y_axis,
             z_axis,
-            center,
-            voltage_per_g,
+            calibration,
             sample_count: 5,
         }
     }
 
-    pub fn get(&self, adc: &mut MCP320X) -> Option<Measurement> {
-        match self.channels().map(|channel| {
-            adc.get_voltage_median(channel, self.sample_count)
-                .map(|voltage| (voltage - self.center) / self.voltage_per_g)
-                .ok()
-        }) {
-            [Some(x), Some(y), Some(z)] => Some([x, y, z]),
+    /// The channels to scan, and how often.
+    pub fn scan_group(&self, period: Duration) -> ScanGroup {
+        ScanGroup {
+            inputs: self.channels().map(Input::from).to_vec(),
+            period,
+            sample_count: self.sample_count,
+        }
+    }
+
+    /// Convert a scan of this accelerometer's group to acceleration.
+    pub fn get(&self, reading: &Reading) -> Option<Measurement> {
+        let voltages: Vec<f64> = reading.voltages().collect();
+        let voltages: Voltages = voltages.try_into().ok()?;
+        Some([0, 1, 2].map(|i| self.calibration.axes[i].to_g(voltages[i])))
+    }
+
+    /// Uncalibrated readings, for calibrating.
+    pub fn get_voltages<A: Adc>(&self, adc: &mut A) -> Option<Voltages> {
+        let inputs = self.channels().map(Input::from);
+        let values = adc.read_many(&inputs, self.sample_count).ok()?;
+        match values[..] {
+            [x, y, z] => Some([x, y, z].map(|value| value * adc.vref())),
             _ => None,
         }
     }
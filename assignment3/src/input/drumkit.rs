@@ -1,8 +1,19 @@
+/**
+ * Turns strikes of the board into drum hits.
+ *
+ * Gravity and slow tilting are removed with a high-pass filter, then each stroke is found as a peak in
+ * the magnitude of what is left. The axis the peak points along picks the instrument.
+ */
 use std::time::{Duration, Instant};
 
-use crate::{hal::mcp320x::MCP320X, input::accelerometer::Accelerometer, sound::Instrument};
+use hat_hal::adc::{Reading, ScanGroup};
 
-#[derive(Debug, Clone, Copy)]
+use crate::{
+    input::accelerometer::{Acceleration, Accelerometer, Measurement},
+    sound::Instrument,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Event {
     A,
     B,
@@ -11,6 +22,17 @@ pub enum Event {
 
 impl Event {
     const ALL: [Event; 3] = [Self::A, Self::B, Self::C];
+
+    /// Classify a stroke by the axis it moved furthest along.
+    ///
+    /// The sign is ignored, so either way along an axis is the same drum. A stroke pushes one way and
+    /// then stops the other, and the peak can be either, depending on how hard the board is stopped.
+    fn from_direction(peak: Measurement) -> Self {
+        let axis = (0..3)
+            .max_by(|&a, &b| peak[a].abs().total_cmp(&peak[b].abs()))
+            .unwrap();
+        Self::ALL[axis]
+    }
 }
 
 impl From<Event> for Instrument {
@@ -23,47 +45,112 @@ impl From<Event> for Instrument {
     }
 }
 
+/// Removes gravity and slow tilting, leaving only fast movements.
+struct HighPass {
+    time_constant: f64,
+    /// Time, input and output of the last sample.
+    prev: Option<(Instant, Measurement, Measurement)>,
+}
+
+impl HighPass {
+    fn update(&mut self, input: Measurement, now: Instant) -> Measurement {
+        let output = match self.prev {
+            Some((time, prev_input, prev_output)) => {
+                let dt = (now - time).as_secs_f64();
+                let a = self.time_constant / (self.time_constant + dt);
+                [0, 1, 2].map(|i| a * (prev_output[i] + input[i] - prev_input[i]))
+            }
+            None => [0.0; 3],
+        };
+        self.prev = Some((now, input, output));
+        output
+    }
+}
+
+/// Where the detector is within a stroke.
+enum Stroke {
+    /// Waiting for the magnitude to cross the threshold.
+    Ready,
+    /// Above the threshold, following the peak.
+    Rising(Measurement, Acceleration),
+    /// Hit was sent at this time, waiting for the magnitude to drop before the next stroke.
+    Falling(Instant),
+}
+
 pub struct Drumkit {
     acc: Accelerometer,
+    filter: HighPass,
 
-    thresholds: [f64; 3],
-    prev: [Option<Instant>; 3],
+    stroke: Stroke,
+    /// Magnitude in g, after removing gravity, that starts a stroke.
+    threshold: Acceleration,
+    prev: Option<Instant>,
 
+    /// Shortest time between hits, to ignore the board ringing.
     timeout: Duration,
 }
 
 impl Drumkit {
-    pub fn new(acc: Accelerometer, thresholds: [f64; 3], timeout: Duration) -> Self {
+    /// Fraction of the threshold the magnitude must drop below, before another stroke can start.
+    const RELEASE: f64 = 0.5;
+    /// Longest wait for the magnitude to drop, so a board left shaking doesn't stop every later hit.
+    const RELEASE_TIMEOUT: Duration = Duration::from_millis(500);
+
+    pub fn new(acc: Accelerometer, threshold: Acceleration, timeout: Duration) -> Self {
         Self {
             acc,
-            thresholds,
-            prev: [None, None, None],
+            filter: HighPass {
+                time_constant: 0.05,
+                prev: None,
+            },
+            stroke: Stroke::Ready,
+            threshold,
+            prev: None,
             timeout,
         }
     }
 
-    pub fn get(&mut self, adc: &mut MCP320X, now: Instant) -> Vec<Event> {
-        match self.acc.get(adc) {
-            Some(vals) => (0..3)
-                .filter_map(|i| {
-                    let val = vals[i];
-                    let threshold = self.thresholds[i];
-                    let prev = self.prev[i];
-                    let event = Event::ALL[i];
-
-                    if prev.is_some_and(|prev| now - prev < self.timeout) {
-                        return None;
-                    }
-
-                    if val > threshold {
-                        self.prev[i] = Some(now);
-                        Some(event)
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
-            None => Vec::new(),
+    /// The accelerometer channels to scan, and how often.
+    pub fn scan_group(&self, period: Duration) -> ScanGroup {
+        self.acc.scan_group(period)
+    }
+
+    /// Find hits in a scan of the accelerometer's group.
+    pub fn get(&mut self, reading: &Reading) -> Vec<Event> {
+        let Some(vals) = self.acc.get(reading) else {
+            return Vec::new();
+        };
+        let now = reading.time;
+
+        let motion = self.filter.update(vals, now);
+        let magnitude = motion.iter().map(|a| a * a).sum::<f64>().sqrt();
+
+        match self.stroke {
+            Stroke::Ready => {
+                if magnitude > self.threshold
+                    && self.prev.is_none_or(|prev| now - prev >= self.timeout)
+                {
+                    self.stroke = Stroke::Rising(motion, magnitude);
+                }
+                Vec::new()
+            }
+            Stroke::Rising(_, peak) if magnitude >= peak => {
+                self.stroke = Stroke::Rising(motion, magnitude);
+                Vec::new()
+            }
+            Stroke::Rising(peak, _) => {
+                self.stroke = Stroke::Falling(now);
+                self.prev = Some(now);
+                vec![Event::from_direction(peak)]
+            }
+            Stroke::Falling(since) => {
+                if magnitude < self.threshold * Self::RELEASE
+                    || now - since >= Self::RELEASE_TIMEOUT
+                {
+                    self.stroke = Stroke::Ready;
+                }
+                Vec::new()
+            }
         }
     }
 }
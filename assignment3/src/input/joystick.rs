@@ -1,6 +1,13 @@
-use crate::hal::mcp320x::{Channel, MCP320X};
+/**
+ * The analog joystick, as a continuous position and as direction events.
+ *
+ * The rest position is found from the first scans, since it is rarely exactly mid-scale.
+ */
+use std::time::Duration;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use hat_hal::adc::{Channel, Reading, ScanGroup};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Direction {
     Center,
     Up,
@@ -9,46 +16,157 @@ pub enum Direction {
     Right,
 }
 
-impl Direction {
-    // x and y in range [0, 1.0]
-    fn new(x: f64, y: f64) -> Self {
-        const THRESHOLD: f64 = 0.25;
+/// Stick position, each axis in [-1.0, 1.0]. Right and up are positive.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
 
-        let dx = (0.5 - x).abs();
-        let dy = (0.5 - y).abs();
+/// How the stick's travel maps to its position.
+#[derive(Debug, Clone, Copy)]
+pub struct Response {
+    /// Travel around the center that reads as 0.
+    pub dead_zone: f64,
+    /// 1.0 is linear. Higher gives finer control near the center.
+    pub exponent: f64,
+}
 
-        if dx < THRESHOLD && dy < THRESHOLD {
-            Self::Center
-        } else if dx > dy {
-            if x > 0.5 { Self::Left } else { Self::Right }
-        } else if y > 0.5 {
-            Self::Up
-        } else {
-            Self::Down
+impl Default for Response {
+    fn default() -> Self {
+        Self {
+            dead_zone: 0.1,
+            exponent: 2.0,
         }
     }
 }
 
+impl Response {
+    fn apply(&self, value: f64) -> f64 {
+        let travel = (value.abs() - self.dead_zone).max(0.0) / (1.0 - self.dead_zone);
+        travel.min(1.0).powf(self.exponent).copysign(value)
+    }
+}
+
+/// Averages the first scans to find the rest position.
+struct Calibration {
+    sum: (f64, f64),
+    count: usize,
+}
+
 pub struct Joystick {
     x_axis: Channel,
     y_axis: Channel,
 
     sample_count: usize,
+    response: Response,
+
+    calibration: Calibration,
+    /// Raw rest position, once calibrated.
+    center: Option<(f64, f64)>,
+    direction: Direction,
 }
 
 impl Joystick {
+    /// Scans averaged for the rest position. The stick must be left alone while they are taken.
+    const CALIBRATION_SCANS: usize = 20;
+    /// Travel along an axis that starts a direction.
+    const PRESS: f64 = 0.5;
+    /// Travel the stick must return below, to go back to the center.
+    const RELEASE: f64 = 0.3;
+
     pub fn new(x_axis: Channel, y_axis: Channel) -> Self {
         Self {
             x_axis,
             y_axis,
             sample_count: 5,
+            response: Response::default(),
+            calibration: Calibration {
+                sum: (0.0, 0.0),
+                count: 0,
+            },
+            center: None,
+            direction: Direction::Center,
         }
     }
 
-    pub fn get(&self, adc: &mut MCP320X) -> Option<Direction> {
-        match [self.x_axis, self.y_axis].map(|channel| adc.get_median(channel, self.sample_count)) {
-            [Ok(x), Ok(y)] => Some(Direction::new(x, y)),
-            _ => None,
+    pub fn set_response(&mut self, response: Response) {
+        self.response = response;
+    }
+
+    /// The channels to scan, and how often.
+    pub fn scan_group(&self, period: Duration) -> ScanGroup {
+        ScanGroup {
+            inputs: vec![self.x_axis.into(), self.y_axis.into()],
+            period,
+            sample_count: self.sample_count,
+        }
+    }
+
+    /// Read the position from a scan of this joystick's group. None while still finding the center.
+    pub fn get(&mut self, reading: &Reading) -> Option<Position> {
+        let [x, y] = reading.values[..] else {
+            return None;
+        };
+
+        let Some((center_x, center_y)) = self.center else {
+            let calibration = &mut self.calibration;
+            calibration.sum = (calibration.sum.0 + x, calibration.sum.1 + y);
+            calibration.count += 1;
+            if calibration.count >= Self::CALIBRATION_SCANS {
+                let count = calibration.count as f64;
+                self.center = Some((calibration.sum.0 / count, calibration.sum.1 / count));
+            }
+            return None;
+        };
+
+        // The x axis reads higher to the left.
+        let position = Position {
+            x: self.response.apply(-Self::scale(x, center_x)),
+            y: self.response.apply(Self::scale(y, center_y)),
+        };
+        self.direction = self.next_direction(position);
+        Some(position)
+    }
+
+    /// The direction the stick is pushed, with hysteresis so it doesn't flicker at the edges.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Scale the raw travel from the center to [-1.0, 1.0], using the room on each side.
+    fn scale(raw: f64, center: f64) -> f64 {
+        let travel = raw - center;
+        let room = if travel >= 0.0 { 1.0 - center } else { center };
+        (travel / room.max(f64::EPSILON)).clamp(-1.0, 1.0)
+    }
+
+    fn next_direction(&self, position: Position) -> Direction {
+        let Position { x, y } = position;
+        let held = match self.direction {
+            Direction::Center => 0.0,
+            Direction::Left => -x,
+            Direction::Right => x,
+            Direction::Up => y,
+            Direction::Down => -y,
+        };
+
+        if x.abs().max(y.abs()) >= Self::PRESS && held < Self::PRESS {
+            if x.abs() > y.abs() {
+                if x > 0.0 {
+                    Direction::Right
+                } else {
+                    Direction::Left
+                }
+            } else if y > 0.0 {
+                Direction::Up
+            } else {
+                Direction::Down
+            }
+        } else if self.direction != Direction::Center && held < Self::RELEASE {
+            Direction::Center
+        } else {
+            self.direction
         }
     }
 }
@@ -0,0 +1,246 @@
+/**
+ * Which action each physical input does.
+ *
+ * Kept as a text file with one `<input> <action> [amount]` line per mapping, like "encoder tempo 1".
+ * Inputs without a line do nothing.
+ */
+use std::{collections::HashMap, fmt, fs, io, path::Path, str::FromStr};
+
+use crate::{
+    input::{drumkit, joystick::Direction},
+    sound::{Instrument, NoteEvent},
+};
+
+/// Button gestures that can be mapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Gesture {
+    Click,
+    DoubleClick,
+    LongPress,
+}
+
+/// A physical input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Control {
+    /// The joystick was pushed in a direction.
+    Joystick(Direction),
+    /// How far the joystick is pushed right, applied every second it is held.
+    JoystickX,
+    /// How far the joystick is pushed up, applied every second it is held.
+    JoystickY,
+    /// Detents turned, with acceleration.
+    Encoder,
+    Button(Gesture),
+    Drum(drumkit::Event),
+}
+
+impl Control {
+    const NAMES: [(&str, Control); 14] = [
+        ("joystick-up", Control::Joystick(Direction::Up)),
+        ("joystick-down", Control::Joystick(Direction::Down)),
+        ("joystick-left", Control::Joystick(Direction::Left)),
+        ("joystick-right", Control::Joystick(Direction::Right)),
+        ("joystick-center", Control::Joystick(Direction::Center)),
+        ("joystick-x", Control::JoystickX),
+        ("joystick-y", Control::JoystickY),
+        ("encoder", Control::Encoder),
+        ("click", Control::Button(Gesture::Click)),
+        ("double-click", Control::Button(Gesture::DoubleClick)),
+        ("long-press", Control::Button(Gesture::LongPress)),
+        ("drum-a", Control::Drum(drumkit::Event::A)),
+        ("drum-b", Control::Drum(drumkit::Event::B)),
+        ("drum-c", Control::Drum(drumkit::Event::C)),
+    ];
+
+    /// Inputs with an amount, rather than single events. Only `tempo`, `volume` and `cutoff` act on them,
+    /// and `bend` on the joystick axes.
+    pub fn is_continuous(self) -> bool {
+        matches!(self, Self::JoystickX | Self::JoystickY | Self::Encoder)
+    }
+
+    fn name(self) -> &'static str {
+        Self::NAMES
+            .iter()
+            .find(|(_, control)| *control == self)
+            .map(|(name, _)| *name)
+            .unwrap()
+    }
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Control {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::NAMES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, control)| *control)
+            .ok_or(())
+    }
+}
+
+/// What an input does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    /// Change the tempo by this many BPM, per detent or unit of the input.
+    Tempo(f64),
+    /// Change the volume by this many percent, per detent or unit of the input.
+    Volume(f64),
+    /// Move the master filter cutoff by this many octaves, per detent or unit of the input.
+    Cutoff(f64),
+    /// Bend the pitch of everything playing by up to this many semitones, following the joystick.
+    /// Kept within two octaves.
+    Bend(f64),
+    NextScore,
+    /// Stop, or resume where it stopped.
+    StartStop,
+    /// Go back to the starting tempo.
+    ResetTempo,
+    Play(Instrument),
+    Quit,
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tempo(amount) => write!(f, "tempo {}", amount),
+            Self::Volume(amount) => write!(f, "volume {}", amount),
+            Self::Cutoff(amount) => write!(f, "cutoff {}", amount),
+            Self::Bend(amount) => write!(f, "bend {}", amount),
+            Self::NextScore => write!(f, "next-score"),
+            Self::StartStop => write!(f, "start-stop"),
+            Self::ResetTempo => write!(f, "reset-tempo"),
+            Self::Play(instrument) => write!(f, "play {}", instrument.to_index()),
+            Self::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = ();
+
+    /// Parse from the name and its argument, like "tempo 1" or "play 0".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let action = match (parts.next(), parts.next()) {
+            (Some("tempo"), Some(amount)) => Self::Tempo(amount.parse().map_err(|_| ())?),
+            (Some("volume"), Some(amount)) => Self::Volume(amount.parse().map_err(|_| ())?),
+            (Some("cutoff"), Some(amount)) => Self::Cutoff(amount.parse().map_err(|_| ())?),
+            (Some("bend"), Some(amount)) => {
+                let max = NoteEvent::MAX_PITCH as f64 / 100.0;
+                Self::Bend(amount.parse::<f64>().map_err(|_| ())?.clamp(-max, max))
+            }
+            (Some("next-score"), None) => Self::NextScore,
+            (Some("start-stop"), None) => Self::StartStop,
+            (Some("reset-tempo"), None) => Self::ResetTempo,
+            (Some("play"), Some(index)) => {
+                Self::Play(Instrument::from_index(index.parse().map_err(|_| ())?))
+            }
+            (Some("quit"), None) => Self::Quit,
+            _ => return Err(()),
+        };
+        match parts.next() {
+            Some(_) => Err(()),
+            None => Ok(action),
+        }
+    }
+}
+
+/// The action for each mapped input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    actions: HashMap<Control, Action>,
+}
+
+impl Mapping {
+    pub fn get(&self, control: Control) -> Option<Action> {
+        self.actions.get(&control).copied()
+    }
+
+    /// Map an input to an action, or to nothing.
+    pub fn set(&mut self, control: Control, action: Option<Action>) {
+        match action {
+            Some(action) => self.actions.insert(control, action),
+            None => self.actions.remove(&control),
+        };
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?).map_err(|line| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid control mapping: \"{}\"", line),
+            )
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    /// Parse the mapping file. Fails with the first bad line.
+    fn parse(text: &str) -> Result<Self, &str> {
+        let mut actions = HashMap::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (control, action) = line.split_once(' ').ok_or(line)?;
+            actions.insert(
+                control.parse().map_err(|_| line)?,
+                action.parse().map_err(|_| line)?,
+            );
+        }
+        Ok(Self { actions })
+    }
+}
+
+impl fmt::Display for Mapping {
+    /// One line per mapping, sorted so the file is stable.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines: Vec<_> = self
+            .actions
+            .iter()
+            .map(|(control, action)| format!("{} {}", control, action))
+            .collect();
+        lines.sort_unstable();
+
+        for line in lines {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Mapping {
+    /// The board's usual controls. Nothing quits, so a stray push can't stop the music.
+    fn default() -> Self {
+        Self {
+            actions: HashMap::from([
+                (Control::JoystickY, Action::Volume(50.0)),
+                (Control::Encoder, Action::Tempo(1.0)),
+                (Control::Button(Gesture::Click), Action::NextScore),
+                (Control::Button(Gesture::DoubleClick), Action::StartStop),
+                (Control::Button(Gesture::LongPress), Action::ResetTempo),
+                (
+                    Control::Drum(drumkit::Event::A),
+                    Action::Play(drumkit::Event::A.into()),
+                ),
+                (
+                    Control::Drum(drumkit::Event::B),
+                    Action::Play(drumkit::Event::B.into()),
+                ),
+                (
+                    Control::Drum(drumkit::Event::C),
+                    Action::Play(drumkit::Event::C.into()),
+                ),
+            ]),
+        }
+    }
+}
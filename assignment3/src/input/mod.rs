@@ -1,3 +1,4 @@
 pub mod accelerometer;
 pub mod drumkit;
 pub mod joystick;
+pub mod mapping;
@@ -1,33 +1,63 @@
+use std::io;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, mpsc};
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
 use crate::{
-    hal::{button::Button, encoder::Encoder, mcp320x::MCP320X},
+    command::LoopCommand,
     input::{
-        accelerometer::Accelerometer,
+        accelerometer::{Accelerometer, Calibration, Voltages},
         drumkit::Drumkit,
         joystick::{Direction, Joystick},
+        mapping::{Action, Control, Gesture, Mapping},
     },
+    midi::{
+        Message,
+        clock::{ClockMaster, ClockMode, ClockSlave},
+        input::MidiIn,
+        output::MidiOut,
+    },
+    options::Options,
     sampler::{JitterInfo, Sampler},
-    sound::{Instrument, load_wav_mono_i16, playback::Playback, score::ScoreType},
+    sound::{
+        Instrument, NoteEvent,
+        effects::{Biquad, Bus, Chain, Compressor, Delay, FilterKind, Reverb},
+        looper::Loop,
+        pattern::PatternLibrary,
+        playback::{AlsaSink, Playback},
+        samples::SampleStore,
+        score::ScoreType,
+        sequencer::Sequencer,
+    },
+    sync::{NetSync, Role},
     udp::UdpConn,
     units::{Bpm, Volume},
 };
 use alsa::PCM;
-use hal::mcp320x::Channel as C;
+use hat_hal::{
+    adc::{self, Channel as C, Reading},
+    button::{Button, Event as ButtonEvent},
+    encoder::{Acceleration, Encoder},
+    mcp3x0x::{Family, Mcp3x0x},
+};
 
 pub mod command;
-pub mod hal;
 pub mod input;
+pub mod midi;
+pub mod options;
 pub mod sampler;
 pub mod server;
 pub mod sound;
+pub mod sync;
 pub mod udp;
 pub mod units;
 
 pub struct App<'a> {
-    adc: MCP320X,
+    adc_thread: Option<JoinHandle<io::Result<()>>>,
+    adc_readings: mpsc::Receiver<Reading>,
+    adc_kill: mpsc::Sender<()>,
     encoder: Encoder,
     button: Button,
     joystick: Joystick,
@@ -36,15 +66,26 @@ pub struct App<'a> {
     udp: Option<UdpConn>,
     server: server::NodeProcess,
 
-    playback: Playback<'a, Instrument>,
+    playback: Playback<Instrument, AlsaSink<'a>>,
+    midi_out: Option<MidiOut>,
+    midi_in: Option<MidiIn>,
+    clock_mode: ClockMode,
+    clock_master: ClockMaster,
+    clock_slave: ClockSlave,
+    sync: Option<NetSync>,
 
     score_index: usize,
-    score: crate::sound::score::Score,
+    sequencer: Sequencer,
+    patterns: PatternLibrary,
     volume: Volume,
     bpm: Bpm,
+    /// Tempo from the options, restored by `Action::ResetTempo`.
+    start_bpm: Bpm,
+    mapping: Mapping,
+    mapping_path: PathBuf,
 
+    /// Direction and time of the last joystick scan.
     prev_joystick: Option<(Instant, Direction)>,
-    joystick_period: Duration,
 
     last_log: Option<Instant>,
     log_period: Duration,
@@ -68,28 +109,44 @@ impl UpdateStatus {
 }
 
 impl<'a> App<'a> {
-    pub fn new(pcm: &'a PCM) -> Self {
-        let adc = MCP320X::new("/dev/spidev0.0", 3.3).expect("ADC creation must work.");
+    /// Scan groups given to the ADC thread.
+    const JOYSTICK_GROUP: usize = 0;
+    const ACCEL_GROUP: usize = 1;
+
+    pub fn new(pcm: &'a PCM, options: &Options) -> Self {
+        let adc =
+            Mcp3x0x::new("/dev/spidev0.0", Family::MCP320X, 3.3).expect("ADC creation must work.");
         let (encoder, button) = {
             use gpiod::*;
             let chip = Chip::new("gpiochip0").expect("GPIO chip must be avaliable.");
 
             let encoder = Options::input([7, 10]) // [GPIO 23, GPIO 24]
                 .active(Active::High)
-                .bias(Bias::PullDown);
+                .bias(Bias::PullDown)
+                .edge(EdgeDetect::Both);
             let encoder = chip
                 .request_lines(encoder)
                 .expect("Encoder pin creation must work.");
             let button = Options::input([17]) // [GPIO 3]
                 .active(Active::Low)
-                .bias(Bias::PullDown);
+                .bias(Bias::PullDown)
+                .edge(EdgeDetect::Both);
             let button = chip
                 .request_lines(button)
                 .expect("Button pin creation must work.");
 
             (
-                Encoder::new(encoder).expect("Encoder creation must work."),
-                Button::new(
+                {
+                    let mut encoder = Encoder::new(encoder).expect("Encoder creation must work.");
+                    encoder.set_acceleration(Acceleration {
+                        threshold: 4.0,
+                        gain: options.encoder_accel,
+                        exponent: 1.5,
+                        max: 20.0,
+                    });
+                    encoder
+                },
+                Button::with_edges(
                     button,
                     Duration::from_millis(20),
                     Duration::from_millis(250),
@@ -111,43 +168,133 @@ impl<'a> App<'a> {
 
         let channels = 1;
         let rate = 44100;
-        let mut playback = Playback::new(
-            pcm,
-            channels,
-            rate,
-            channels as usize * 128,
-            channels as usize * 512,
-        )
-        .expect("Playback start must work.");
-
-        playback.add_instrument(
-            load_wav_mono_i16("./sounds/100051__menegass__gui-drum-bd-hard.wav"),
-            Instrument::BassDrum,
-        );
-        playback.add_instrument(
-            load_wav_mono_i16("./sounds/100063__menegass__gui-drum-tom-hi-soft.wav"),
-            Instrument::HiHat,
+        let transfer_size = channels as usize * 128;
+        let buffer_size = channels as usize * 512;
+        let sink = AlsaSink::new(pcm, channels, rate, buffer_size + transfer_size)
+            .expect("Playback start must work.");
+        let mut playback = Playback::new(sink, channels, rate, transfer_size);
+
+        playback.set_chain(
+            Bus::Master,
+            Chain::serial(vec![
+                Box::new(Biquad::new(FilterKind::LowPass, rate, f64::INFINITY)),
+                Box::new(Compressor::new(rate)),
+            ]),
         );
-        playback.add_instrument(
-            load_wav_mono_i16("./sounds/100059__menegass__gui-drum-snare-soft.wav"),
-            Instrument::Snare,
+        playback.set_chain(
+            Bus::Send,
+            Chain::parallel(vec![
+                Box::new(Delay::new(rate, options.tempo)),
+                Box::new(Reverb::new(rate)),
+            ]),
         );
 
+        let samples = SampleStore::new(options.sample_budget);
+        for (path, instrument) in [
+            (
+                "./sounds/100051__menegass__gui-drum-bd-hard.wav",
+                Instrument::BassDrum,
+            ),
+            (
+                "./sounds/100063__menegass__gui-drum-tom-hi-soft.wav",
+                Instrument::HiHat,
+            ),
+            (
+                "./sounds/100059__menegass__gui-drum-snare-soft.wav",
+                Instrument::Snare,
+            ),
+        ] {
+            playback.add_instrument(
+                samples.add(path).expect("Drum samples must load."),
+                instrument,
+            );
+        }
+        if let Some(path) = &options.loop_path {
+            match samples.add(path) {
+                Ok(sample) => playback.set_loop(Some(Loop::new(
+                    sample,
+                    options.loop_bars,
+                    options.loop_mode,
+                ))),
+                Err(e) => eprintln!("Warning: could not load loop {}: {}", path.display(), e),
+            }
+        }
+
+        // Reload edited samples while playing. The thread stops with the store.
+        samples.watch(Duration::from_secs(1));
+
+        let midi_out = options.midi_out.as_ref().and_then(|device| {
+            MidiOut::open(device, options.midi_channel)
+                .inspect_err(|e| eprintln!("Warning: could not open MIDI output {}: {}", device, e))
+                .ok()
+        });
+
+        if options.midi_clock == ClockMode::Master && midi_out.is_none() {
+            eprintln!("Warning: MIDI clock master needs a MIDI output.");
+        }
+
+        let midi_in = options.midi_in.as_ref().and_then(|device| {
+            MidiIn::open(device)
+                .inspect_err(|e| eprintln!("Warning: could not open MIDI input {}: {}", device, e))
+                .ok()
+        });
+
+        let sync = options.sync.and_then(|role| {
+            NetSync::open(role, options.sync_addr)
+                .inspect_err(|e| eprintln!("Warning: could not open network sync: {}", e))
+                .ok()
+        });
+
         let joystick = Joystick::new(C::CH0, C::CH1);
-        let acc = Accelerometer::new(C::CH2, C::CH3, C::CH4, 1.57, 0.42);
-        let drumkit = Drumkit::new(acc, [2.0, 1.0, 1.0], Duration::from_millis(100));
+        let calibration = match Calibration::load(&options.calibration) {
+            Ok(calibration) => calibration,
+            Err(e) => {
+                eprintln!(
+                    "Warning: could not load accelerometer calibration {}: {}. Run with --calibrate.",
+                    options.calibration.display(),
+                    e
+                );
+                Calibration::default()
+            }
+        };
+        let acc = accelerometer(calibration);
+
+        let mapping = match Mapping::load(&options.controls) {
+            Ok(mapping) => mapping,
+            Err(e) => {
+                eprintln!(
+                    "Warning: could not load control mapping {}: {}. Using the defaults.",
+                    options.controls.display(),
+                    e
+                );
+                Mapping::default()
+            }
+        };
+        let drumkit = Drumkit::new(acc, 1.0, Duration::from_millis(30));
+
+        // Scan the inputs off the audio thread, the accelerometer fast enough to catch a strike's peak.
+        let (adc_thread, adc_readings, adc_kill) = adc::make_scan_thread(
+            adc,
+            vec![
+                joystick.scan_group(Duration::from_millis(10)),
+                drumkit.scan_group(Duration::from_millis(2)),
+            ],
+        );
 
         // Prepare initial score and state
         let score_index = 1usize;
-        let score = ScoreType::from_index(score_index).apply();
+        let sequencer = Sequencer::new(ScoreType::from_index(score_index).apply());
+        let patterns = PatternLibrary::load("./patterns");
         let volume = Volume::try_from(20).unwrap();
-        let bpm = Bpm::try_from(120).unwrap();
+        let bpm = options.tempo;
 
         let audio_sampler = Sampler::new();
         let accel_sampler = Sampler::new();
 
         App {
-            adc,
+            adc_thread: Some(adc_thread),
+            adc_readings,
+            adc_kill,
             encoder,
             button,
             joystick,
@@ -157,14 +304,23 @@ impl<'a> App<'a> {
             server,
 
             playback,
+            midi_out,
+            midi_in,
+            clock_mode: options.midi_clock,
+            clock_master: ClockMaster::default(),
+            clock_slave: ClockSlave::default(),
+            sync,
 
             score_index,
-            score,
+            sequencer,
+            patterns,
             volume,
             bpm,
+            start_bpm: bpm,
+            mapping,
+            mapping_path: options.controls.clone(),
 
             prev_joystick: None,
-            joystick_period: Duration::from_millis(10),
 
             last_log: None,
             log_period: Duration::from_millis(1000),
@@ -178,76 +334,75 @@ impl<'a> App<'a> {
         pcm.prepare().expect("PCM prepare must work.");
 
         // Run the update loop, until quit
-        while self.update(pcm).do_continue() {}
+        while self.update().do_continue() {}
 
         pcm.drain().expect("PCM drain must work.");
         self.end();
     }
 
-    fn update(&mut self, pcm: &'a PCM) -> UpdateStatus {
+    fn update(&mut self) -> UpdateStatus {
         let now = Instant::now();
 
-        // Handle joystick changes for volume/break
-        if let Some(event) = self.joystick.get(&mut self.adc)
-            && self
-                .prev_joystick
-                .is_none_or(|(time, prev)| prev != event || (now - time) > self.joystick_period)
-        {
-            if let Some(delta) = match event {
-                Direction::Up => Some(0.05),
-                Direction::Down => Some(-0.05),
-                Direction::Left => return UpdateStatus::Quit,
-                _ => None,
-            } {
-                self.set_volume(self.volume.saturating_add(delta));
+        // Take the scans from the ADC thread
+        let mut joystick = Vec::new();
+        let mut hits = Vec::new();
+        for reading in self.adc_readings.try_iter() {
+            match reading.group {
+                Self::JOYSTICK_GROUP => joystick.extend(
+                    self.joystick
+                        .get(&reading)
+                        .map(|position| (reading.time, position, self.joystick.direction())),
+                ),
+                Self::ACCEL_GROUP => {
+                    self.accel_sampler.add_sample(reading.time);
+                    hits.extend(self.drumkit.get(&reading));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(handle) = self.adc_thread.take_if(|handle| handle.is_finished()) {
+            match handle.join() {
+                Ok(Err(e)) => eprintln!("ADC thread error: {}", e),
+                Ok(Ok(())) => eprintln!("ADC thread stopped."),
+                Err(_) => eprintln!("ADC thread panicked."),
             }
-            self.prev_joystick = Some((now, event));
         }
 
-        let mut notes: Vec<Instrument> = Vec::new();
+        let mut notes: Vec<NoteEvent> = Vec::new();
+
+        // The joystick's position is applied for as long as it is held, and its direction when it changes.
+        for (time, position, direction) in joystick {
+            self.apply_position(Control::JoystickX, position.x);
+            self.apply_position(Control::JoystickY, position.y);
+            if let Some((prev_time, prev_direction)) = self.prev_joystick {
+                let dt = (time - prev_time).as_secs_f64();
+                self.apply_control(Control::JoystickX, position.x * dt, &mut notes);
+                self.apply_control(Control::JoystickY, position.y * dt, &mut notes);
+                if direction != prev_direction
+                    && !self
+                        .apply_control(Control::Joystick(direction), 1.0, &mut notes)
+                        .do_continue()
+                {
+                    return UpdateStatus::Quit;
+                }
+            }
+            self.prev_joystick = Some((time, direction));
+        }
 
         // Handle events over UDP
         let mut reply: Option<(Arc<str>, SocketAddr)> = None;
-
         if let Some(ref udp) = self.udp {
             match udp.try_recv_command() {
                 Ok(Some((cmd, addr))) => {
-                    reply = Some((
-                        if let Some(cmd) = cmd {
-                            match cmd {
-                                command::Command::Mode(mode) => {
-                                    if let Some(mode) = mode {
-                                        self.set_score(ScoreType::from_index(mode as usize));
-                                    }
-                                    format!("{}", self.score.t.to_index()).into()
-                                }
-                                command::Command::Volume(volume) => {
-                                    if let Some(volume) = volume {
-                                        self.set_volume(volume);
-                                    }
-                                    format!("{}", self.volume.as_percentage()).into()
-                                }
-                                command::Command::Tempo(bpm) => {
-                                    if let Some(bpm) = bpm {
-                                        self.set_tempo(bpm);
-                                    }
-                                    format!("{}", self.bpm.as_f64()).into()
-                                }
-                                command::Command::Play(trigger) => {
-                                    if let Some(trigger) = trigger {
-                                        notes.push(trigger);
-                                    }
-                                    Arc::from("OK")
-                                }
-                                command::Command::Stop => {
-                                    return UpdateStatus::Quit;
-                                }
-                            }
-                        } else {
-                            Arc::from("OK")
+                    let r = match cmd {
+                        Some(cmd) => match self.handle_command(cmd, &mut notes) {
+                            Some(r) => r,
+                            None => return UpdateStatus::Quit,
                         },
-                        addr,
-                    ));
+                        None => Arc::from("OK"),
+                    };
+                    reply = Some((r, addr));
                 }
                 Err(e) => eprintln!("UDP receive error: {}", e),
                 Ok(None) => {}
@@ -256,38 +411,96 @@ impl<'a> App<'a> {
 
         if let Some((r, addr)) = reply
             && let Some(udp) = &mut self.udp
+            && let Err(e) = udp.send_reply(r, addr)
         {
-            if let Err(e) = udp.send_reply(r, addr) {
-                eprintln!("UDP send reply error: {}", e);
-            }
+            eprintln!("UDP send reply error: {}", e);
         }
 
-        // Handle bpm update from encoder
-        self.encoder.update();
-        self.bpm = self.bpm.saturating_add(self.encoder.get_acc_delta() as f64);
+        // Handle MIDI input
+        if let Some(midi_in) = &mut self.midi_in {
+            match midi_in.read(now) {
+                Ok(messages) => {
+                    for (message, time) in messages {
+                        self.handle_midi(message, time, &mut notes);
+                    }
+                }
+                Err(e) => eprintln!("MIDI input error: {}", e),
+            }
+        }
 
-        // Handle changing the chosen score.
-        if self.button.update(now).is_some() {
-            self.score_index += 1;
-            self.set_score(ScoreType::from_index(self.score_index));
+        // Handle the encoder and button, through the control mapping
+        if let Err(e) = self.encoder.update() {
+            eprintln!("Encoder error: {}", e);
+        }
+        let delta = self.encoder.get_delta();
+        let mut controls = vec![(Control::Encoder, delta)];
+        let button_events = self.button.update(now).unwrap_or_else(|e| {
+            eprintln!("Button error: {}", e);
+            Vec::new()
+        });
+        for event in button_events {
+            let gesture = match event {
+                ButtonEvent::Click => Gesture::Click,
+                ButtonEvent::DoubleClick => Gesture::DoubleClick,
+                ButtonEvent::LongPress => Gesture::LongPress,
+                ButtonEvent::Pressed | ButtonEvent::Repeat | ButtonEvent::Released(_) => continue,
+            };
+            controls.push((Control::Button(gesture), 1.0));
+        }
+        for (control, amount) in controls {
+            if !self
+                .apply_control(control, amount, &mut notes)
+                .do_continue()
+            {
+                return UpdateStatus::Quit;
+            }
         }
 
         // Get the score notes
-        notes.extend(
-            self.score
-                .update(self.bpm, now)
-                .into_iter()
-                .map(|e| e.instrument),
-        );
+        notes.extend(self.sequencer.update(self.bpm, now));
+
+        // Share or follow the playhead over the network
+        if let Some(sync) = &mut self.sync {
+            match sync.update(&mut self.sequencer, self.bpm, now) {
+                Ok(bpm) => self.bpm = bpm,
+                Err(e) => eprintln!("Sync error: {}", e),
+            }
+        }
+
+        // Keep the backing loop on the score's bar lines
+        let bar_lines = self.sequencer.take_bar_lines();
+        if let Some(looper) = self.playback.looper_mut() {
+            for bar_length in bar_lines {
+                if let Err(e) = looper.bar(bar_length) {
+                    eprintln!("Loop error: {}", e);
+                }
+            }
+        }
+
+        // Send MIDI clock from the sequencer
+        if self.clock_mode == ClockMode::Master
+            && let Some(midi_out) = &mut self.midi_out
+        {
+            for (delay, message) in self.clock_master.update(
+                self.sequencer.beats(),
+                self.sequencer.is_playing(),
+                self.bpm,
+            ) {
+                if let Err(e) = midi_out.send_after(message, delay, now) {
+                    eprintln!("MIDI clock error: {}", e);
+                }
+            }
+        }
 
         // Get the drumkit notes
-        notes.extend({
-            self.accel_sampler.add_sample(now);
-            self.drumkit
-                .get(&mut self.adc, now)
-                .into_iter()
-                .map(Instrument::from)
-        });
+        for hit in hits {
+            if !self
+                .apply_control(Control::Drum(hit), 1.0, &mut notes)
+                .do_continue()
+            {
+                return UpdateStatus::Quit;
+            }
+        }
 
         // Handle logging
         if self
@@ -298,13 +511,30 @@ impl<'a> App<'a> {
             self.log(now);
         }
 
-        for instrument in notes {
-            self.playback.start_sound(instrument);
+        for note in notes {
+            if let Err(e) = self
+                .playback
+                .start_note(note.instrument, note.gain(), note.pitch)
+            {
+                eprintln!("Sample error: {}", e);
+            }
+            if let Some(midi_out) = &mut self.midi_out
+                && let Err(e) = midi_out.note(note.instrument, note.velocity, now)
+            {
+                eprintln!("MIDI output error: {}", e);
+            }
+        }
+
+        if let Some(midi_out) = &mut self.midi_out
+            && let Err(e) = midi_out.update(now)
+        {
+            eprintln!("MIDI output error: {}", e);
         }
 
+        self.playback.set_tempo(self.bpm);
         let audio_frames = self
             .playback
-            .update(pcm, self.volume)
+            .update(self.volume)
             .expect("Playback update must work.");
 
         if audio_frames > 0 {
@@ -314,10 +544,212 @@ impl<'a> App<'a> {
         UpdateStatus::Continue
     }
 
+    /// Apply a command from the control protocol, and get the reply. None means quit.
+    fn handle_command(
+        &mut self,
+        cmd: command::Command,
+        notes: &mut Vec<NoteEvent>,
+    ) -> Option<Arc<str>> {
+        Some(match cmd {
+            command::Command::Mode(mode) => {
+                if let Some(mode) = mode {
+                    self.set_score(ScoreType::from_index(mode as usize));
+                }
+                format!("{}", self.sequencer.score().t.to_index()).into()
+            }
+            command::Command::Volume(volume) => {
+                if let Some(volume) = volume {
+                    self.set_volume(volume);
+                }
+                format!("{}", self.volume.as_percentage()).into()
+            }
+            command::Command::Tempo(bpm) => {
+                if let Some(bpm) = bpm {
+                    self.set_tempo(bpm);
+                }
+                format!("{}", self.bpm.as_f64()).into()
+            }
+            command::Command::Play(trigger) => {
+                if let Some(trigger) = trigger {
+                    notes.push(NoteEvent::new(trigger));
+                }
+                Arc::from("OK")
+            }
+            command::Command::Position => format!("{}", self.sequencer.score().position()).into(),
+            command::Command::Song(song) => {
+                if let Some(song) = song {
+                    self.sequencer.play_song(song);
+                }
+                self.sequencer
+                    .song()
+                    .map(|song| song.to_string())
+                    .unwrap_or("null".to_owned())
+                    .into()
+            }
+            command::Command::Toggle(toggle) => toggle
+                .and_then(|(instrument, step)| {
+                    self.sequencer.next_score_mut().toggle(instrument, step)
+                })
+                .map(|set| if set { "1" } else { "0" })
+                .unwrap_or("ERR")
+                .into(),
+            command::Command::Clear(instrument) => match instrument {
+                Some(instrument) => {
+                    self.sequencer.next_score_mut().clear(instrument);
+                    Arc::from("OK")
+                }
+                None => Arc::from("ERR"),
+            },
+            command::Command::Length(bars) => {
+                if let Some(bars) = bars {
+                    self.sequencer.next_score_mut().set_bars(bars);
+                }
+                format!("{}", self.sequencer.next_score().bars()).into()
+            }
+            command::Command::Grid(instrument) => {
+                let score = self.sequencer.next_score();
+                match instrument {
+                    Some(instrument) => score.grid(instrument).into(),
+                    None => (0..3)
+                        .map(Instrument::from_index)
+                        .map(|i| format!("{} {}", i.to_index(), score.grid(i)))
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                        .into(),
+                }
+            }
+            command::Command::Save(name) => match name {
+                Some(name) => match self.patterns.save(&name, self.sequencer.next_score()) {
+                    Ok(()) => Arc::from("OK"),
+                    Err(e) => format!("ERR {}", e).into(),
+                },
+                None => Arc::from("ERR"),
+            },
+            command::Command::Load(name) => match name {
+                Some(name) => match self.patterns.get(&name) {
+                    Some(score) => {
+                        self.sequencer.queue_pattern(score);
+                        Arc::from("OK")
+                    }
+                    None => Arc::from("ERR"),
+                },
+                None => {
+                    let mut names: Vec<_> = self.patterns.names().collect();
+                    names.sort_unstable();
+                    names.join(" ").into()
+                }
+            },
+            command::Command::Map(change) => match change {
+                Some((control, action)) => {
+                    self.mapping.set(control, action);
+                    match self.mapping.save(&self.mapping_path) {
+                        Ok(()) => Arc::from("OK"),
+                        Err(e) => format!("ERR {}", e).into(),
+                    }
+                }
+                None => self.mapping.to_string().trim_end().into(),
+            },
+            command::Command::Fx(change) => match change {
+                Some((bus, effect, param, value)) => self
+                    .playback
+                    .chain_mut(bus)
+                    .set(&effect, &param, value)
+                    .map(|value| value.to_string())
+                    .unwrap_or("ERR".to_owned())
+                    .into(),
+                None => [Bus::Master, Bus::Send]
+                    .into_iter()
+                    .flat_map(|bus| {
+                        self.playback
+                            .chain(bus)
+                            .describe()
+                            .into_iter()
+                            .map(move |line| format!("{} {}", bus, line))
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .into(),
+            },
+            command::Command::Send(send) => {
+                if let Some((instrument, level)) = send {
+                    self.playback.set_send(instrument, level as f32 / 100.0);
+                }
+                (0..3)
+                    .map(Instrument::from_index)
+                    .map(|i| {
+                        let level = (self.playback.send(&i) * 100.0).round();
+                        format!("{} {}", i.to_index(), level)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .into()
+            }
+            command::Command::Tune(tune) => {
+                if let Some((instrument, semitones, cents)) = tune {
+                    self.playback.set_tuning(instrument, semitones, cents);
+                }
+                (0..3)
+                    .map(Instrument::from_index)
+                    .map(|i| {
+                        let (semitones, cents) = self.playback.tuning(&i);
+                        format!("{} {} {}", i.to_index(), semitones, cents)
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .into()
+            }
+            command::Command::Loop(command) => match self.playback.looper_mut() {
+                Some(looper) => {
+                    match command {
+                        Some(LoopCommand::Start) => looper.start(),
+                        Some(LoopCommand::Stop) => looper.stop(),
+                        Some(LoopCommand::Mute) => looper.set_muted(true),
+                        Some(LoopCommand::Unmute) => looper.set_muted(false),
+                        Some(LoopCommand::Mode(mode)) => looper.set_mode(mode),
+                        None => {}
+                    }
+                    let muted = if looper.is_muted() { " muted" } else { "" };
+                    format!("{} {}{}", looper.state(), looper.mode(), muted).into()
+                }
+                None => "ERR".into(),
+            },
+            command::Command::Stop => return None,
+        })
+    }
+
+    fn handle_midi(&mut self, message: Message, time: Instant, notes: &mut Vec<NoteEvent>) {
+        match message {
+            Message::NoteOn { note, velocity, .. } => notes.push(NoteEvent {
+                instrument: Instrument::from_midi_note(note),
+                velocity,
+                pitch: 0,
+            }),
+            Message::ProgramChange { program, .. } => {
+                self.set_score(ScoreType::from_index(program as usize))
+            }
+            Message::Clock if self.clock_mode == ClockMode::Slave => {
+                if let Some(bpm) = self.clock_slave.tick(time) {
+                    self.set_tempo(bpm);
+                }
+            }
+            Message::Start if self.clock_mode == ClockMode::Slave => self.sequencer.start(),
+            Message::Continue if self.clock_mode == ClockMode::Slave => self.sequencer.resume(),
+            Message::Stop if self.clock_mode == ClockMode::Slave => {
+                self.sequencer.stop();
+                self.clock_slave.reset();
+            }
+            Message::NoteOff { .. }
+            | Message::Clock
+            | Message::Start
+            | Message::Continue
+            | Message::Stop => {}
+        }
+    }
+
     fn log(&mut self, now: Instant) {
         println!(
             "{} {} {}, Audio {}, Accel {}",
-            self.score.t,
+            self.sequencer.score().t,
             self.bpm,
             self.volume,
             self.audio_sampler
@@ -333,10 +765,62 @@ impl<'a> App<'a> {
         );
     }
 
+    /// Change the score on the next bar line.
     fn set_score(&mut self, score: ScoreType) {
-        let prev_beat = self.score.get_beat();
-        self.score = score.apply();
-        self.score.set_beat(prev_beat);
+        self.sequencer.queue(score);
+    }
+
+    /// Do the mapped action for an input. `amount` is how far a continuous input moved, 1.0 for the others.
+    fn apply_control(
+        &mut self,
+        control: Control,
+        amount: f64,
+        notes: &mut Vec<NoteEvent>,
+    ) -> UpdateStatus {
+        let Some(action) = self.mapping.get(control) else {
+            return UpdateStatus::Continue;
+        };
+        if control.is_continuous()
+            && !matches!(
+                action,
+                Action::Tempo(_) | Action::Volume(_) | Action::Cutoff(_)
+            )
+        {
+            return UpdateStatus::Continue;
+        }
+
+        match action {
+            Action::Tempo(scale) if amount != 0.0 => {
+                self.set_tempo(self.bpm.saturating_add(scale * amount))
+            }
+            Action::Volume(scale) if amount != 0.0 => {
+                self.set_volume(self.volume.saturating_add((scale * amount) as f32))
+            }
+            Action::Cutoff(octaves) if amount != 0.0 => {
+                let master = self.playback.chain_mut(Bus::Master);
+                if let Some(cutoff) = master.get("filter", "cutoff") {
+                    master.set("filter", "cutoff", cutoff * (octaves * amount).exp2());
+                }
+            }
+            Action::Tempo(_) | Action::Volume(_) | Action::Cutoff(_) | Action::Bend(_) => {}
+            Action::NextScore => {
+                self.score_index += 1;
+                self.set_score(ScoreType::from_index(self.score_index));
+            }
+            Action::StartStop if self.sequencer.is_playing() => self.sequencer.stop(),
+            Action::StartStop => self.sequencer.resume(),
+            Action::ResetTempo => self.set_tempo(self.start_bpm),
+            Action::Play(instrument) => notes.push(NoteEvent::new(instrument)),
+            Action::Quit => return UpdateStatus::Quit,
+        }
+        UpdateStatus::Continue
+    }
+
+    /// Do the mapped action that follows where a joystick axis is, rather than how long it is held.
+    fn apply_position(&mut self, control: Control, position: f64) {
+        if let Some(Action::Bend(semitones)) = self.mapping.get(control) {
+            self.playback.set_bend(semitones * 100.0 * position);
+        }
     }
 
     fn set_volume(&mut self, volume: Volume) {
@@ -347,15 +831,130 @@ impl<'a> App<'a> {
         self.bpm = bpm;
     }
 
-    fn end(self) {
+    fn end(mut self) {
+        if let Some(midi_out) = &mut self.midi_out {
+            let _ = midi_out.flush();
+        }
+        if let Err(e) = self.encoder.end() {
+            eprintln!("Encoder error: {}", e);
+        }
+        let _ = self.adc_kill.send(());
+        if let Some(handle) = self.adc_thread.take() {
+            let _ = handle.join();
+        }
         self.server.end();
     }
 }
 
 fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, Options::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if options.calibrate {
+        run_calibration(&options);
+        return;
+    }
+
+    if let Some(seconds) = options.sync_probe {
+        run_sync_probe(&options, seconds);
+        return;
+    }
+
     let pcm =
         PCM::new("plughw:1,0", alsa::Direction::Playback, false).expect("PCM creation must work");
 
-    let app = App::new(&pcm);
+    let app = App::new(&pcm, &options);
     app.run(&pcm);
 }
+
+/// Run only the sequencer and network sync, printing the playhead against the system clock.
+fn run_sync_probe(options: &Options, seconds: f64) {
+    let role = options.sync.unwrap_or(Role::Leader);
+    let mut sync = NetSync::open(role, options.sync_addr).expect("Sync socket must open.");
+    let mut sequencer = Sequencer::new(ScoreType::Standard.apply());
+    let mut bpm = options.tempo;
+
+    let start = Instant::now();
+    while start.elapsed().as_secs_f64() < seconds {
+        let now = Instant::now();
+        let wall = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("System clock must be after 1970.");
+
+        sequencer.update(bpm, now);
+        match sync.update(&mut sequencer, bpm, now) {
+            Ok(b) => bpm = b,
+            Err(e) => eprintln!("Sync error: {}", e),
+        }
+
+        println!(
+            "{} {} {} {}",
+            wall.as_micros(),
+            sequencer.playhead(),
+            sequencer.score().length(),
+            bpm.as_f64()
+        );
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+fn accelerometer(calibration: Calibration) -> Accelerometer {
+    Accelerometer::new(C::CH2, C::CH3, C::CH4, calibration)
+}
+
+/// Have the user hold the board with each axis pointing up then down, and save the calibration.
+fn run_calibration(options: &Options) {
+    let mut adc =
+        Mcp3x0x::new("/dev/spidev0.0", Family::MCP320X, 3.3).expect("ADC creation must work.");
+    let acc = accelerometer(Calibration::default());
+
+    let mut measure = |orientation: String| -> Voltages {
+        println!("Hold the board with {}, then press Enter.", orientation);
+        let mut line = String::new();
+        std::io::stdin()
+            .read_line(&mut line)
+            .expect("Reading stdin must work.");
+
+        // Average over half a second, so a slightly shaky hand doesn't matter.
+        let count = 50;
+        let mut sum = [0.0; 3];
+        for _ in 0..count {
+            let voltages = acc
+                .get_voltages(&mut adc)
+                .expect("Accelerometer read must work.");
+            for (total, voltage) in sum.iter_mut().zip(voltages) {
+                *total += voltage;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        sum.map(|total| total / count as f64)
+    };
+
+    let readings = ["X", "Y", "Z"].map(|axis| {
+        (
+            measure(format!("the {} axis pointing up", axis)),
+            measure(format!("the {} axis pointing down", axis)),
+        )
+    });
+
+    let Some(calibration) = Calibration::from_orientations(readings) else {
+        eprintln!("Calibration failed: an axis barely changed between up and down.");
+        std::process::exit(1);
+    };
+
+    calibration
+        .save(&options.calibration)
+        .expect("Calibration file must be writable.");
+    for (axis, c) in ["X", "Y", "Z"].iter().zip(calibration.axes) {
+        println!(
+            "{}: {:.3} V at 0 g, {:.3} V/g",
+            axis, c.offset, c.voltage_per_g
+        );
+    }
+    println!("Saved to {}", options.calibration.display());
+}
@@ -0,0 +1,260 @@
+/**
+ * MIDI clock, which ticks 24 times per quarter note, with start/stop/continue transport messages.
+ */
+use std::time::{Duration, Instant};
+
+use crate::{midi::Message, sound::Beat, units::Bpm};
+
+pub const TICKS_PER_BEAT: u32 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockMode {
+    /// Ignore clock, and don't send it.
+    Off,
+    /// Send clock from the sequencer.
+    Master,
+    /// Follow incoming clock and transport.
+    #[default]
+    Slave,
+}
+
+/// Sends clock ticks as the sequencer moves, and transport messages when it starts or stops.
+///
+/// Ticks are worked out a little ahead, each with its delay from now, so they can be scheduled
+/// evenly spaced rather than sent in a burst on every update.
+#[derive(Debug, Default)]
+pub struct ClockMaster {
+    /// The next tick to schedule, counted from the last start.
+    ticks: u64,
+    last_beats: Beat,
+    playing: bool,
+}
+
+impl ClockMaster {
+    /// How far ahead ticks are scheduled. Must be longer than the time between updates.
+    pub const AHEAD: Duration = Duration::from_millis(20);
+
+    /// Get the messages to send, and how long from now to send each, given the total beats the sequencer has played.
+    pub fn update(&mut self, beats: Beat, playing: bool, bpm: Bpm) -> Vec<(Duration, Message)> {
+        let mut messages = Vec::new();
+
+        // Played beats only go backwards when the sequencer starts again from the top.
+        let restarted = beats < self.last_beats;
+        if restarted {
+            self.ticks = 0;
+        }
+
+        let transport = match (self.playing, playing) {
+            (false, true) if self.ticks == 0 => Some(Message::Start),
+            (false, true) => Some(Message::Continue),
+            (true, false) => Some(Message::Stop),
+            (true, true) if restarted => Some(Message::Start),
+            _ => None,
+        };
+        messages.extend(transport.map(|message| (Duration::ZERO, message)));
+        if self.playing && !playing {
+            // Ticks scheduled past the stop are dropped with it, so carry on from the stop.
+            self.ticks = (beats * TICKS_PER_BEAT as Beat).floor() as u64 + 1;
+        }
+        self.playing = playing;
+        self.last_beats = beats;
+
+        if playing {
+            let seconds_per_beat = 60.0 / f64::from(bpm);
+            let ahead = Self::AHEAD.as_secs_f64() / seconds_per_beat;
+            // Tick n falls on beat n / 24, so the first is on the downbeat with the start.
+            let end = ((beats + ahead) * TICKS_PER_BEAT as Beat).floor() as u64 + 1;
+            messages.extend((self.ticks..end).map(|tick| {
+                let beat = tick as Beat / TICKS_PER_BEAT as Beat;
+                let delay = Duration::from_secs_f64((beat - beats).max(0.0) * seconds_per_beat);
+                (delay, Message::Clock)
+            }));
+            self.ticks = self.ticks.max(end);
+        }
+
+        messages
+    }
+}
+
+/// Follows incoming clock. The tick period is smoothed, with late or early ticks rejected, so jitter doesn't wobble the tempo.
+///
+/// Ticks read together can share a time. Those are taken as evenly spread since the time before,
+/// so a bunch counts for its number of ticks rather than a zero period.
+#[derive(Debug)]
+pub struct ClockSlave {
+    /// Time of the last bunch of ticks that has been measured.
+    prev: Option<Instant>,
+    /// Time of the newest ticks, and how many share it.
+    last: Option<(Instant, u32)>,
+    period: Option<Duration>,
+    count: u32,
+
+    /// Weight of each new tick in the smoothed period.
+    smoothing: f64,
+}
+
+impl Default for ClockSlave {
+    fn default() -> Self {
+        Self {
+            prev: None,
+            last: None,
+            period: None,
+            count: 0,
+            smoothing: 0.1,
+        }
+    }
+}
+
+impl ClockSlave {
+    /// Ticks needed before the tempo is trusted.
+    const SETTLE_TICKS: u32 = 6;
+
+    /// Record a clock tick. Returns the tempo once it has settled.
+    ///
+    /// A tick is measured once a later one arrives, as only then is it known how many share its time.
+    pub fn tick(&mut self, now: Instant) -> Option<Bpm> {
+        match self.last {
+            Some((time, ref mut ticks)) if time == now => *ticks += 1,
+            Some((time, ticks)) => {
+                if let Some(prev) = self.prev {
+                    self.measure((time - prev) / ticks, ticks);
+                }
+                self.prev = Some(time);
+                self.last = Some((now, 1));
+            }
+            None => self.last = Some((now, 1)),
+        }
+
+        let period = self.period?;
+        (self.count >= Self::SETTLE_TICKS)
+            .then(|| Bpm::saturating_from(60.0 / (period.as_secs_f64() * TICKS_PER_BEAT as f64)))
+    }
+
+    /// Add `ticks` ticks, each `delta` apart, to the smoothed period.
+    fn measure(&mut self, delta: Duration, ticks: u32) {
+        let period = match self.period {
+            None => delta,
+            // A tick at less than half or more than double the period is a glitch or a tempo jump.
+            Some(period) if delta > period * 2 || delta < period / 2 => {
+                self.count = 0;
+                delta
+            }
+            Some(period) => {
+                let keep = (1.0 - self.smoothing).powi(ticks as i32);
+                period.mul_f64(keep) + delta.mul_f64(1.0 - keep)
+            }
+        };
+        self.period = Some(period);
+        self.count += ticks;
+    }
+
+    /// Forget the timing, such as after the clock stops.
+    pub fn reset(&mut self) {
+        *self = Self {
+            smoothing: self.smoothing,
+            ..Self::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bpm(value: u32) -> Bpm {
+        Bpm::try_from(value).unwrap()
+    }
+
+    #[test]
+    fn master_schedules_ticks_ahead_at_their_own_times() {
+        let mut master = ClockMaster::default();
+        // At 125 bpm, a tick is every 20 ms, so 20 ms ahead reaches the second one.
+        let messages = master.update(0.0, true, bpm(125));
+        assert_eq!(
+            messages,
+            [
+                (Duration::ZERO, Message::Start),
+                (Duration::ZERO, Message::Clock),
+                (Duration::from_millis(20), Message::Clock),
+            ]
+        );
+
+        // One and a half ticks later, only the third tick is new, half a tick off.
+        let messages = master.update(1.5 / TICKS_PER_BEAT as Beat, true, bpm(125));
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].1, Message::Clock);
+        assert!(messages[0].0.abs_diff(Duration::from_millis(10)) < Duration::from_micros(1));
+    }
+
+    #[test]
+    fn master_continues_from_the_stop() {
+        let mut master = ClockMaster::default();
+        master.update(0.0, true, bpm(125));
+        let beats = 1.0 / TICKS_PER_BEAT as Beat;
+        master.update(beats, true, bpm(125));
+        assert_eq!(
+            master.update(beats, false, bpm(125)),
+            [(Duration::ZERO, Message::Stop)]
+        );
+
+        // The tick already scheduled past the stop was dropped, so it is sent again.
+        let messages = master.update(beats, true, bpm(125));
+        assert_eq!(messages[0], (Duration::ZERO, Message::Continue));
+        assert_eq!(messages[1].1, Message::Clock);
+        assert!(messages[1].0.abs_diff(Duration::from_millis(20)) < Duration::from_micros(1));
+    }
+
+    /// Ticks at `bpm`, read in bunches every `poll`, each stamped with the time it was read.
+    fn bunched(bpm: f64, poll: Duration, count: u32) -> Vec<Instant> {
+        let start = Instant::now();
+        let period = Duration::from_secs_f64(60.0 / (bpm * TICKS_PER_BEAT as f64));
+        (1..=count)
+            .map(|tick| {
+                let polls = (period * tick).as_nanos().div_ceil(poll.as_nanos());
+                start + poll * polls as u32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn slave_follows_steady_ticks() {
+        let mut slave = ClockSlave::default();
+        let start = Instant::now();
+        let period = Duration::from_secs_f64(60.0 / (120.0 * TICKS_PER_BEAT as f64));
+        let tempos: Vec<_> = (0..10)
+            .map(|tick| slave.tick(start + period * tick))
+            .collect();
+
+        assert!(
+            tempos[..ClockSlave::SETTLE_TICKS as usize]
+                .iter()
+                .all(Option::is_none)
+        );
+        let bpm = f64::from(tempos.last().unwrap().unwrap());
+        assert!((bpm - 120.0).abs() < 0.01, "{}", bpm);
+    }
+
+    #[test]
+    fn slave_follows_ticks_read_in_bunches() {
+        let mut slave = ClockSlave::default();
+        // Around 2 or 3 ticks a poll, and never a whole number.
+        let times = bunched(120.0, Duration::from_millis(50), 24 * 8);
+        assert!(times.windows(2).any(|pair| pair[0] == pair[1]));
+
+        let tempos: Vec<_> = times.into_iter().map(|time| slave.tick(time)).collect();
+        // Once settled, bunches never look like a tempo jump.
+        let settled = tempos.iter().position(Option::is_some).unwrap();
+        assert!(tempos[settled..].iter().all(Option::is_some));
+
+        // Each bunch still wobbles the tempo a little, but it averages out over the last few beats.
+        let mut tempos: Vec<_> = tempos[tempos.len() - 4 * 24..]
+            .iter()
+            .map(|bpm| f64::from(bpm.unwrap()))
+            .collect();
+        // One tempo per bunch, rather than per tick.
+        tempos.dedup();
+        let mean = tempos.iter().sum::<f64>() / tempos.len() as f64;
+        assert!((mean - 120.0).abs() < 0.5, "{}", mean);
+        assert!(tempos.iter().all(|bpm| (bpm - 120.0).abs() < 6.0));
+    }
+}
@@ -0,0 +1,130 @@
+/**
+ * MIDI input from the ALSA sequencer or a raw MIDI device.
+ *
+ * The sequencer port can be fed locally with `aconnect` or `aplaymidi -p beat_box`.
+ */
+use std::{
+    ffi::CString,
+    io::{self, Read},
+    time::Instant,
+};
+
+use crate::midi::{Message, Parser, alsa_error};
+use alsa::{
+    Direction, Rawmidi, Seq,
+    seq::{EventType, MidiEvent, PortCap, PortInfo, PortType},
+};
+
+enum Backend {
+    /// A virtual sequencer port that other programs can connect to.
+    Seq {
+        seq: Seq,
+        /// Queue whose real time stamps incoming events.
+        queue: i32,
+        decoder: MidiEvent,
+    },
+    Raw(Rawmidi),
+}
+
+/// Non-blocking MIDI input. Sequencer messages are stamped with the time they arrived,
+/// raw MIDI messages with the time they were read.
+pub struct MidiIn {
+    backend: Backend,
+    parser: Parser,
+}
+
+impl MidiIn {
+    /// Name of the sequencer client and port.
+    pub const CLIENT_NAME: &str = "beat_box in";
+
+    /// Open an input. "seq" creates a virtual sequencer port, anything else is a raw MIDI device name like "hw:1,0,0".
+    pub fn open(device: &str) -> io::Result<Self> {
+        let backend = if device == "seq" {
+            // Opened both ways, since starting the queue is an output event.
+            let seq = Seq::open(None, None, true).map_err(alsa_error)?;
+            let name = CString::new(Self::CLIENT_NAME).unwrap();
+            seq.set_client_name(&name).map_err(alsa_error)?;
+
+            let queue = seq.alloc_queue().map_err(alsa_error)?;
+            seq.control_queue(queue, EventType::Start, 0, None)
+                .map_err(alsa_error)?;
+            seq.drain_output().map_err(alsa_error)?;
+
+            let mut port = PortInfo::empty().map_err(alsa_error)?;
+            port.set_name(&name);
+            port.set_capability(PortCap::WRITE | PortCap::SUBS_WRITE);
+            port.set_type(PortType::MIDI_GENERIC | PortType::APPLICATION);
+            port.set_timestamping(true);
+            port.set_timestamp_real(true);
+            port.set_timestamp_queue(queue);
+            seq.create_port(&port).map_err(alsa_error)?;
+
+            let decoder = MidiEvent::new(16).map_err(alsa_error)?;
+            decoder.enable_running_status(false);
+            Backend::Seq {
+                seq,
+                queue,
+                decoder,
+            }
+        } else {
+            Backend::Raw(Rawmidi::new(device, Direction::Capture, true).map_err(alsa_error)?)
+        };
+
+        Ok(Self {
+            backend,
+            parser: Parser::default(),
+        })
+    }
+
+    /// Read every message that has arrived since the last call.
+    pub fn read(&mut self, now: Instant) -> io::Result<Vec<(Message, Instant)>> {
+        let mut messages = Vec::new();
+
+        match &mut self.backend {
+            Backend::Seq {
+                seq,
+                queue,
+                decoder,
+            } => {
+                // Events are stamped in queue time, which is `queue_now` at `now`.
+                let queue_now = seq
+                    .get_queue_status(*queue)
+                    .map_err(alsa_error)?
+                    .get_real_time();
+                let mut input = seq.input();
+                while input.event_input_pending(true).map_err(alsa_error)? > 0 {
+                    let mut event = input.event_input().map_err(alsa_error)?;
+                    let time = event
+                        .get_time()
+                        .and_then(|time| now.checked_sub(queue_now.saturating_sub(time)))
+                        .unwrap_or(now);
+                    let mut buf = [0u8; 16];
+                    // Events without a MIDI byte form (like port subscriptions) fail to decode.
+                    if let Ok(n) = decoder.decode(&mut buf, &mut event) {
+                        messages.extend(
+                            buf[..n]
+                                .iter()
+                                .filter_map(|&byte| self.parser.push(byte))
+                                .map(|message| (message, time)),
+                        );
+                    }
+                }
+            }
+            Backend::Raw(raw) => {
+                let avail = raw.status().map_err(alsa_error)?.get_avail();
+                if avail > 0 {
+                    let mut buf = vec![0u8; avail];
+                    let n = raw.io().read(&mut buf)?;
+                    messages.extend(
+                        buf[..n]
+                            .iter()
+                            .filter_map(|&byte| self.parser.push(byte))
+                            .map(|message| (message, now)),
+                    );
+                }
+            }
+        }
+
+        Ok(messages)
+    }
+}
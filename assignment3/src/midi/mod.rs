@@ -0,0 +1,163 @@
+/**
+ * MIDI messages, and the General MIDI drum map.
+ */
+use std::io;
+
+use crate::sound::Instrument;
+
+pub mod clock;
+pub mod input;
+pub mod output;
+
+/// MIDI channel, stored as 0-15 but shown to users as 1-16.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Channel(u8);
+
+impl Channel {
+    /// General MIDI percussion is on channel 10.
+    pub const DRUMS: Self = Self(9);
+
+    pub fn index(self) -> u8 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for Channel {
+    type Error = ();
+
+    /// Create from a user-facing channel number in [1, 16].
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        (1..=16)
+            .contains(&value)
+            .then_some(Self(value as u8 - 1))
+            .ok_or(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message {
+    NoteOn {
+        channel: Channel,
+        note: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        channel: Channel,
+        note: u8,
+    },
+    ProgramChange {
+        channel: Channel,
+        program: u8,
+    },
+    Clock,
+    Start,
+    Continue,
+    Stop,
+}
+
+impl Message {
+    /// Encode as raw MIDI bytes.
+    pub fn to_bytes(self) -> Vec<u8> {
+        match self {
+            Message::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => vec![0x90 | channel.0, note & 0x7F, velocity & 0x7F],
+            Message::NoteOff { channel, note } => vec![0x80 | channel.0, note & 0x7F, 0],
+            Message::ProgramChange { channel, program } => vec![0xC0 | channel.0, program & 0x7F],
+            Message::Clock => vec![0xF8],
+            Message::Start => vec![0xFA],
+            Message::Continue => vec![0xFB],
+            Message::Stop => vec![0xFC],
+        }
+    }
+}
+
+/// Turns a raw MIDI byte stream into messages. Handles running status and real-time bytes mid-message.
+#[derive(Debug, Default)]
+pub struct Parser {
+    status: Option<u8>,
+    data: Vec<u8>,
+}
+
+impl Parser {
+    pub fn push(&mut self, byte: u8) -> Option<Message> {
+        match byte {
+            // Real-time messages can appear anywhere, and don't affect running status.
+            0xF8 => return Some(Message::Clock),
+            0xFA => return Some(Message::Start),
+            0xFB => return Some(Message::Continue),
+            0xFC => return Some(Message::Stop),
+            0xF9 | 0xFD..=0xFF => return None,
+            // System common messages cancel running status. They are not used here.
+            0xF0..=0xF7 => {
+                self.status = None;
+                self.data.clear();
+                return None;
+            }
+            0x80..=0xEF => {
+                self.status = Some(byte);
+                self.data.clear();
+                return None;
+            }
+            _ => {}
+        }
+
+        let status = self.status?;
+        self.data.push(byte);
+
+        let channel = Channel(status & 0x0F);
+        let length = match status & 0xF0 {
+            0xC0 | 0xD0 => 1,
+            _ => 2,
+        };
+        if self.data.len() < length {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data);
+
+        match status & 0xF0 {
+            // A note-on with no velocity is a note-off.
+            0x90 if data[1] > 0 => Some(Message::NoteOn {
+                channel,
+                note: data[0],
+                velocity: data[1],
+            }),
+            0x80 | 0x90 => Some(Message::NoteOff {
+                channel,
+                note: data[0],
+            }),
+            0xC0 => Some(Message::ProgramChange {
+                channel,
+                program: data[0],
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Instrument {
+    /// Note number in the General MIDI percussion key map.
+    pub fn to_gm_drum(self) -> u8 {
+        match self {
+            Instrument::BassDrum => 36,
+            Instrument::Snare => 38,
+            Instrument::HiHat => 42,
+        }
+    }
+
+    /// Pick the instrument for an incoming note. Notes outside the drum map are spread over the instruments.
+    pub fn from_midi_note(note: u8) -> Self {
+        match note {
+            35 | 36 => Instrument::BassDrum,
+            37 | 38 | 40 => Instrument::Snare,
+            42 | 44 | 46 => Instrument::HiHat,
+            other => Instrument::from_index(other as usize),
+        }
+    }
+}
+
+pub fn alsa_error(e: alsa::Error) -> io::Error {
+    io::Error::other(e)
+}
@@ -0,0 +1,299 @@
+/**
+ * MIDI output over the ALSA sequencer or a raw MIDI device.
+ *
+ * The sequencer port can be checked locally with `aseqdump -p beat_box`.
+ */
+use std::{
+    ffi::CString,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use alsa::{
+    Direction, Rawmidi, Seq,
+    seq::{EventType, MidiEvent, PortCap, PortType, Remove, RemoveEvents},
+};
+
+use crate::{
+    midi::{Channel, Message, alsa_error},
+    sound::Instrument,
+};
+
+enum Backend {
+    /// A virtual sequencer port that other programs can subscribe to.
+    Seq {
+        seq: Seq,
+        port: i32,
+        /// Queue for messages sent later, timed by the kernel.
+        queue: i32,
+        encoder: MidiEvent,
+    },
+    /// A raw MIDI device, written byte for byte.
+    Raw(Box<dyn Write>),
+}
+
+/// Raw MIDI only lends out a writer, so this owns the device and borrows one per write.
+struct RawWriter(Rawmidi);
+
+impl Write for RawWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.io().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.io().flush()
+    }
+}
+
+/// Sends drum notes, with the note-off following a fixed time after each note-on.
+pub struct MidiOut {
+    backend: Backend,
+    channel: Channel,
+
+    note_length: Duration,
+    /// Messages waiting for `update`, such as note-offs.
+    pending: Vec<(Instant, Message)>,
+}
+
+impl MidiOut {
+    /// Name of the sequencer client and port.
+    pub const CLIENT_NAME: &str = "beat_box";
+
+    /// Open an output. "seq" creates a virtual sequencer port, anything else is a raw MIDI device name like "hw:1,0,0".
+    pub fn open(device: &str, channel: Channel) -> io::Result<Self> {
+        let backend = if device == "seq" {
+            let seq = Seq::open(None, Some(Direction::Playback), true).map_err(alsa_error)?;
+            let name = CString::new(Self::CLIENT_NAME).unwrap();
+            seq.set_client_name(&name).map_err(alsa_error)?;
+            let port = seq
+                .create_simple_port(
+                    &name,
+                    PortCap::READ | PortCap::SUBS_READ,
+                    PortType::MIDI_GENERIC | PortType::APPLICATION,
+                )
+                .map_err(alsa_error)?;
+            let queue = seq.alloc_queue().map_err(alsa_error)?;
+            seq.control_queue(queue, EventType::Start, 0, None)
+                .map_err(alsa_error)?;
+            seq.drain_output().map_err(alsa_error)?;
+            let encoder = MidiEvent::new(16).map_err(alsa_error)?;
+            Backend::Seq {
+                seq,
+                port,
+                queue,
+                encoder,
+            }
+        } else {
+            let raw = Rawmidi::new(device, Direction::Playback, true).map_err(alsa_error)?;
+            Backend::Raw(Box::new(RawWriter(raw)))
+        };
+
+        Ok(Self::with_backend(backend, channel))
+    }
+
+    fn with_backend(backend: Backend, channel: Channel) -> Self {
+        Self {
+            backend,
+            channel,
+            note_length: Duration::from_millis(50),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn send(&mut self, message: Message) -> io::Result<()> {
+        if message == Message::Stop {
+            // Clock ticks waiting for raw MIDI would otherwise go out after the stop, and again on continue.
+            self.pending
+                .retain(|&(_, message)| message != Message::Clock);
+        }
+
+        let bytes = message.to_bytes();
+        match &mut self.backend {
+            Backend::Seq {
+                seq,
+                port,
+                queue,
+                encoder,
+            } => {
+                if message == Message::Stop {
+                    // Nothing scheduled should follow a stop.
+                    let remove = RemoveEvents::new().map_err(alsa_error)?;
+                    remove.set_condition(Remove::OUTPUT);
+                    remove.set_queue(*queue);
+                    seq.remove_events(remove).map_err(alsa_error)?;
+                }
+
+                let (_, event) = encoder.encode(&bytes).map_err(alsa_error)?;
+                if let Some(mut event) = event {
+                    event.set_source(*port);
+                    event.set_subs();
+                    event.set_direct();
+                    seq.event_output_direct(&mut event).map_err(alsa_error)?;
+                }
+                Ok(())
+            }
+            Backend::Raw(raw) => raw.write_all(&bytes),
+        }
+    }
+
+    /// Send a message `delay` from `now`. The sequencer times it exactly. Raw MIDI has no queue,
+    /// so there it goes out on the first `update` after it is due.
+    pub fn send_after(
+        &mut self,
+        message: Message,
+        delay: Duration,
+        now: Instant,
+    ) -> io::Result<()> {
+        if delay.is_zero() {
+            return self.send(message);
+        }
+
+        match &mut self.backend {
+            Backend::Seq {
+                seq,
+                port,
+                queue,
+                encoder,
+            } => {
+                let (_, event) = encoder.encode(&message.to_bytes()).map_err(alsa_error)?;
+                if let Some(mut event) = event {
+                    event.set_source(*port);
+                    event.set_subs();
+                    event.schedule_real(*queue, true, delay);
+                    seq.event_output(&mut event).map_err(alsa_error)?;
+                    seq.drain_output().map_err(alsa_error)?;
+                }
+                Ok(())
+            }
+            Backend::Raw(_) => {
+                self.pending.push((now + delay, message));
+                Ok(())
+            }
+        }
+    }
+
+    /// Play a drum hit. The note-off is sent by a later `update`.
+    pub fn note(&mut self, instrument: Instrument, velocity: u8, now: Instant) -> io::Result<()> {
+        let note = instrument.to_gm_drum();
+        self.send(Message::NoteOn {
+            channel: self.channel,
+            note,
+            velocity,
+        })?;
+        let off = Message::NoteOff {
+            channel: self.channel,
+            note,
+        };
+        self.pending.push((now + self.note_length, off));
+        Ok(())
+    }
+
+    /// Send any messages that are due. Fails with the first message that can't be sent,
+    /// after trying the rest, so one failure can't leave notes hanging.
+    pub fn update(&mut self, now: Instant) -> io::Result<()> {
+        let (mut due, pending): (Vec<_>, _) =
+            self.pending.drain(..).partition(|&(time, _)| time <= now);
+        self.pending = pending;
+
+        due.sort_by_key(|&(time, _)| time);
+        let mut result = Ok(());
+        for (_, message) in due {
+            if let Err(e) = self.send(message) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// Send every pending note-off now, and drop any other waiting messages.
+    /// Like `update`, tries every note-off before failing.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+        for (_, message) in std::mem::take(&mut self.pending) {
+            if let Message::NoteOff { .. } = message
+                && let Err(e) = self.send(message)
+            {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    /// Keeps what was written, failing the first `fail` writes.
+    #[derive(Clone, Default)]
+    struct TestWriter {
+        written: Rc<RefCell<Vec<u8>>>,
+        fail: Rc<RefCell<usize>>,
+    }
+
+    impl Write for TestWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut fail = self.fail.borrow_mut();
+            if *fail > 0 {
+                *fail -= 1;
+                return Err(io::Error::other("test write failure"));
+            }
+            self.written.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn raw() -> (MidiOut, TestWriter) {
+        let writer = TestWriter::default();
+        let out = MidiOut::with_backend(Backend::Raw(Box::new(writer.clone())), Channel::DRUMS);
+        (out, writer)
+    }
+
+    fn note_off(instrument: Instrument) -> Message {
+        Message::NoteOff {
+            channel: Channel::DRUMS,
+            note: instrument.to_gm_drum(),
+        }
+    }
+
+    #[test]
+    fn a_failed_send_still_sends_the_other_due_messages() {
+        let (mut out, writer) = raw();
+        let start = Instant::now();
+        out.note(Instrument::Snare, 100, start).unwrap();
+        out.note(Instrument::HiHat, 100, start).unwrap();
+        writer.written.borrow_mut().clear();
+
+        *writer.fail.borrow_mut() = 1;
+        assert!(out.update(start + Duration::from_secs(1)).is_err());
+        assert_eq!(
+            *writer.written.borrow(),
+            note_off(Instrument::HiHat).to_bytes()
+        );
+        assert!(out.pending.is_empty());
+    }
+
+    #[test]
+    fn stop_drops_clock_ticks_waiting_for_raw_midi() {
+        let (mut out, writer) = raw();
+        let start = Instant::now();
+        out.send_after(Message::Clock, Duration::from_millis(10), start)
+            .unwrap();
+        out.note(Instrument::Snare, 100, start).unwrap();
+        out.send(Message::Stop).unwrap();
+        writer.written.borrow_mut().clear();
+
+        // The note still ends, but no tick follows the stop.
+        out.update(start + Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            *writer.written.borrow(),
+            note_off(Instrument::Snare).to_bytes()
+        );
+    }
+}
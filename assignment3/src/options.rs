@@ -0,0 +1,182 @@
+/**
+ * Command line options.
+ */
+use std::{net::SocketAddr, path::PathBuf};
+
+use crate::{
+    midi::{self, clock::ClockMode},
+    sound::looper::LoopMode,
+    sync::Role,
+    units::Bpm,
+};
+
+pub struct Options {
+    /// MIDI output device: "seq" for a virtual sequencer port, or a raw MIDI name like "hw:1,0,0".
+    pub midi_out: Option<String>,
+    /// MIDI input device, named the same way as `midi_out`.
+    pub midi_in: Option<String>,
+    pub midi_channel: midi::Channel,
+    pub midi_clock: ClockMode,
+    pub tempo: Bpm,
+    /// How much faster the encoder changes the tempo when spun quickly. 0 for one BPM per detent.
+    pub encoder_accel: f64,
+    /// Lead or follow other units over the network.
+    pub sync: Option<Role>,
+    /// Leader: where to send sync messages. Follower: the port to listen on.
+    pub sync_addr: SocketAddr,
+    /// Run without hardware for this many seconds, printing the playhead. For testing sync.
+    pub sync_probe: Option<f64>,
+    /// Accelerometer calibration file, loaded at startup.
+    pub calibration: PathBuf,
+    /// Run the accelerometer calibration, and save it instead of starting.
+    pub calibrate: bool,
+    /// Control mapping file, loaded at startup and saved when a mapping is changed.
+    pub controls: PathBuf,
+    /// Bytes of samples kept in memory.
+    pub sample_budget: usize,
+    /// Backing loop sample, started over the protocol.
+    pub loop_path: Option<PathBuf>,
+    /// Bars the loop covers.
+    pub loop_bars: u32,
+    pub loop_mode: LoopMode,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            midi_out: None,
+            midi_in: None,
+            midi_channel: midi::Channel::DRUMS,
+            midi_clock: ClockMode::default(),
+            tempo: Bpm::try_from(120).unwrap(),
+            encoder_accel: 0.5,
+            sync: None,
+            sync_addr: SocketAddr::from(([255, 255, 255, 255], 12346)),
+            sync_probe: None,
+            calibration: PathBuf::from("./accelerometer.cal"),
+            calibrate: false,
+            controls: PathBuf::from("./controls.map"),
+            sample_budget: 64 << 20,
+            loop_path: None,
+            loop_bars: 2,
+            loop_mode: LoopMode::Stretch,
+        }
+    }
+}
+
+impl Options {
+    pub const USAGE: &str = r#"Usage: beat_box [options]
+  --midi-out <seq|hw:X,Y,Z>  send notes to a virtual sequencer port or a raw MIDI device.
+  --midi-channel <1-16>      MIDI channel for notes (default 10, General MIDI drums).
+  --midi-in <seq|hw:X,Y,Z>   play notes, change score and follow clock from a sequencer port or raw MIDI device.
+  --midi-clock <off|master|slave>
+                             send clock on the MIDI output, or follow clock on the MIDI input (default slave).
+  --tempo <bpm>              starting tempo (default 120).
+  --encoder-accel <gain>     how much fast encoder turns speed up tempo changes, 0 to turn off (default 0.5).
+  --sync <leader|follower>   share tempo, score and beat with other units over UDP.
+  --sync-addr <ip:port>      leader: where to send, follower: port to listen on (default 255.255.255.255:12346).
+  --sync-probe <seconds>     run without audio or hardware, printing "<unix us> <beat> <length> <bpm>" lines.
+  --calibration <path>       accelerometer calibration file (default ./accelerometer.cal).
+  --calibrate                hold the board in six orientations to calibrate the accelerometer, then exit.
+  --controls <path>          control mapping file, one "<input> <action>" per line (default ./controls.map).
+  --sample-budget <MiB>      memory for samples, least recently played are reloaded when needed (default 64).
+  --loop <path>              backing loop sample, started with the "loop start" command.
+  --loop-bars <n>            bars the loop covers (default 2).
+  --loop-mode <stretch|retrigger>
+                             fit the loop to the tempo by changing its speed, or restart it every n bars (default stretch).
+"#;
+
+    /// Parse the options, not including the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+
+            match arg.as_str() {
+                "--midi-out" => options.midi_out = Some(value()?),
+                "--midi-in" => options.midi_in = Some(value()?),
+                "--midi-channel" => {
+                    options.midi_channel = value()?
+                        .parse::<u32>()
+                        .ok()
+                        .and_then(|n| midi::Channel::try_from(n).ok())
+                        .ok_or("MIDI channel must be in [1, 16]")?
+                }
+                "--midi-clock" => {
+                    options.midi_clock = match value()?.as_str() {
+                        "off" => ClockMode::Off,
+                        "master" => ClockMode::Master,
+                        "slave" => ClockMode::Slave,
+                        _ => return Err("MIDI clock must be off, master or slave".to_owned()),
+                    }
+                }
+                "--tempo" => {
+                    options.tempo = value()?
+                        .parse::<u32>()
+                        .ok()
+                        .and_then(|n| Bpm::try_from(n).ok())
+                        .ok_or("Tempo must be in [40, 300]")?
+                }
+                "--encoder-accel" => {
+                    options.encoder_accel = value()?
+                        .parse()
+                        .ok()
+                        .filter(|g: &f64| *g >= 0.0)
+                        .ok_or("Encoder acceleration must be a non-negative number")?
+                }
+                "--sync" => {
+                    options.sync = Some(match value()?.as_str() {
+                        "leader" => Role::Leader,
+                        "follower" => Role::Follower,
+                        _ => return Err("Sync must be leader or follower".to_owned()),
+                    })
+                }
+                "--sync-addr" => {
+                    options.sync_addr = value()?
+                        .parse()
+                        .map_err(|_| "Sync address must be <ip:port>")?
+                }
+                "--sync-probe" => {
+                    options.sync_probe = Some(
+                        value()?
+                            .parse()
+                            .ok()
+                            .filter(|s: &f64| *s > 0.0)
+                            .ok_or("Sync probe time must be a positive number of seconds")?,
+                    )
+                }
+                "--calibration" => options.calibration = PathBuf::from(value()?),
+                "--calibrate" => options.calibrate = true,
+                "--controls" => options.controls = PathBuf::from(value()?),
+                "--sample-budget" => {
+                    options.sample_budget = value()?
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|mib| mib.checked_mul(1 << 20))
+                        .ok_or("Sample budget must be a whole number of MiB")?
+                }
+                "--loop" => options.loop_path = Some(PathBuf::from(value()?)),
+                "--loop-bars" => {
+                    options.loop_bars = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&n| n > 0)
+                        .ok_or("Loop bars must be a positive whole number")?
+                }
+                "--loop-mode" => {
+                    options.loop_mode = value()?
+                        .parse()
+                        .map_err(|_| "Loop mode must be stretch or retrigger")?
+                }
+                other => return Err(format!("Unknown option: {}", other)),
+            }
+        }
+
+        Ok(options)
+    }
+}
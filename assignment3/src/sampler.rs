@@ -35,6 +35,12 @@ impl Display for JitterInfo {
     }
 }
 
+impl Default for Sampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Sampler {
     /// Create a basic sampler with history period of 1.0 second.
     pub fn new() -> Self {
@@ -0,0 +1,668 @@
+/**
+ * Audio effects, run on the mix in `Playback`.
+ *
+ * Each effect works on interleaved frames of samples in [-1.0, 1.0], in place, and has named parameters
+ * that can be set over the control protocol. Effects are strung together in a `Chain`.
+ */
+use std::{f32::consts::PI, fmt, str::FromStr};
+
+use crate::units::Bpm;
+
+/// A named setting of an effect, kept within its range.
+#[derive(Debug, Clone, Copy)]
+pub struct Param {
+    pub name: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub value: f64,
+}
+
+impl Param {
+    const fn new(name: &'static str, min: f64, max: f64, value: f64) -> Self {
+        Self {
+            name,
+            min,
+            max,
+            value,
+        }
+    }
+}
+
+pub trait Effect: Send {
+    fn name(&self) -> &'static str;
+
+    fn params(&self) -> &[Param];
+
+    fn params_mut(&mut self) -> &mut [Param];
+
+    /// Recalculate anything derived from the parameters, after one changes.
+    fn update_params(&mut self) {}
+
+    /// Follow the tempo, for effects timed in beats.
+    fn set_tempo(&mut self, _bpm: Bpm) {}
+
+    /// Process interleaved frames in place.
+    fn process(&mut self, frames: &mut [f32], channels: usize);
+
+    fn get(&self, name: &str) -> Option<f64> {
+        self.params()
+            .iter()
+            .find(|param| param.name == name)
+            .map(|param| param.value)
+    }
+
+    /// Set a parameter, clamped to its range. Returns the value it was set to.
+    fn set(&mut self, name: &str, value: f64) -> Option<f64> {
+        let param = self
+            .params_mut()
+            .iter_mut()
+            .find(|param| param.name == name)?;
+        param.value = value.clamp(param.min, param.max);
+        let value = param.value;
+        self.update_params();
+        Some(value)
+    }
+}
+
+/// Which chain of effects in `Playback`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    /// Run on the whole mix.
+    Master,
+    /// Run on the instruments' sends, then added to the mix.
+    Send,
+}
+
+impl fmt::Display for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bus::Master => write!(f, "master"),
+            Bus::Send => write!(f, "send"),
+        }
+    }
+}
+
+impl FromStr for Bus {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "master" => Ok(Bus::Master),
+            "send" => Ok(Bus::Send),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Effects run one after another, or side by side with their outputs summed.
+pub struct Chain {
+    effects: Vec<Box<dyn Effect>>,
+    parallel: bool,
+    /// The input, kept while each effect runs on its own copy, when parallel.
+    input: Vec<f32>,
+    /// Input copy for each effect, when parallel.
+    scratch: Vec<f32>,
+}
+
+impl Chain {
+    /// Each effect processes the output of the one before.
+    pub fn serial(effects: Vec<Box<dyn Effect>>) -> Self {
+        Self {
+            effects,
+            parallel: false,
+            input: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// Each effect processes the same input, and their outputs are summed. Used for sends.
+    pub fn parallel(effects: Vec<Box<dyn Effect>>) -> Self {
+        Self {
+            effects,
+            parallel: true,
+            input: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn process(&mut self, frames: &mut [f32], channels: usize) {
+        if !self.parallel {
+            for effect in &mut self.effects {
+                effect.process(frames, channels);
+            }
+            return;
+        }
+
+        self.input.clear();
+        self.input.extend_from_slice(frames);
+        frames.fill(0.0);
+        for effect in &mut self.effects {
+            self.scratch.clear();
+            self.scratch.extend_from_slice(&self.input);
+            effect.process(&mut self.scratch, channels);
+            for (out, wet) in frames.iter_mut().zip(&self.scratch) {
+                *out += wet;
+            }
+        }
+    }
+
+    pub fn set_tempo(&mut self, bpm: Bpm) {
+        for effect in &mut self.effects {
+            effect.set_tempo(bpm);
+        }
+    }
+
+    pub fn effect_mut(&mut self, name: &str) -> Option<&mut dyn Effect> {
+        self.effects
+            .iter_mut()
+            .find(|effect| effect.name() == name)
+            .map(|effect| effect.as_mut() as &mut dyn Effect)
+    }
+
+    pub fn get(&self, effect: &str, param: &str) -> Option<f64> {
+        self.effects
+            .iter()
+            .find(|e| e.name() == effect)
+            .and_then(|e| e.get(param))
+    }
+
+    /// Set a parameter of an effect. Returns the value it was set to.
+    pub fn set(&mut self, effect: &str, param: &str, value: f64) -> Option<f64> {
+        self.effect_mut(effect)?.set(param, value)
+    }
+
+    /// Every parameter, as "<effect> <param> <value>" lines.
+    pub fn describe(&self) -> Vec<String> {
+        self.effects
+            .iter()
+            .flat_map(|effect| {
+                effect
+                    .params()
+                    .iter()
+                    .map(|param| format!("{} {} {}", effect.name(), param.name, param.value))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    LowPass,
+    HighPass,
+    BandPass,
+}
+
+/// Two pole filter, from the RBJ audio EQ cookbook.
+pub struct Biquad {
+    kind: FilterKind,
+    rate: f32,
+    params: [Param; 2],
+    /// b0, b1, b2, a1, a2, normalized by a0.
+    coefficients: [f32; 5],
+    /// x1, x2, y1, y2 for each channel.
+    state: Vec<[f32; 4]>,
+    /// A low pass at its top cutoff passes everything, rather than rolling off near Nyquist.
+    open: bool,
+}
+
+impl Biquad {
+    const CUTOFF: usize = 0;
+    const Q: usize = 1;
+
+    pub fn new(kind: FilterKind, rate: u32, cutoff: f64) -> Self {
+        // Keep below Nyquist, where the coefficients stop making sense.
+        let max_cutoff = rate as f64 * 0.45;
+        let mut filter = Self {
+            kind,
+            rate: rate as f32,
+            params: [
+                Param::new("cutoff", 20.0, max_cutoff, cutoff.clamp(20.0, max_cutoff)),
+                Param::new("q", 0.1, 20.0, std::f64::consts::FRAC_1_SQRT_2),
+            ],
+            coefficients: [1.0, 0.0, 0.0, 0.0, 0.0],
+            state: Vec::new(),
+            open: false,
+        };
+        filter.update_params();
+        filter
+    }
+}
+
+impl Effect for Biquad {
+    fn name(&self) -> &'static str {
+        "filter"
+    }
+
+    fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut [Param] {
+        &mut self.params
+    }
+
+    fn update_params(&mut self) {
+        let cutoff = &self.params[Self::CUTOFF];
+        self.open = self.kind == FilterKind::LowPass && cutoff.value >= cutoff.max;
+
+        let w0 = 2.0 * PI * self.params[Self::CUTOFF].value as f32 / self.rate;
+        let alpha = w0.sin() / (2.0 * self.params[Self::Q].value as f32);
+        let cos = w0.cos();
+
+        let (b0, b1, b2) = match self.kind {
+            FilterKind::LowPass => ((1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0),
+            FilterKind::HighPass => ((1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0),
+            FilterKind::BandPass => (alpha, 0.0, -alpha),
+        };
+        let a0 = 1.0 + alpha;
+        self.coefficients = [
+            b0 / a0,
+            b1 / a0,
+            b2 / a0,
+            -2.0 * cos / a0,
+            (1.0 - alpha) / a0,
+        ];
+    }
+
+    fn process(&mut self, frames: &mut [f32], channels: usize) {
+        self.state.resize(channels, [0.0; 4]);
+        if self.open {
+            // Keep the history current, so closing the filter again doesn't click.
+            for frame in frames.chunks(channels) {
+                for (&x, [x1, x2, y1, y2]) in frame.iter().zip(self.state.iter_mut()) {
+                    (*x2, *x1) = (*x1, x);
+                    (*y2, *y1) = (*y1, x);
+                }
+            }
+            return;
+        }
+        let [b0, b1, b2, a1, a2] = self.coefficients;
+
+        for frame in frames.chunks_mut(channels) {
+            for (x, [x1, x2, y1, y2]) in frame.iter_mut().zip(self.state.iter_mut()) {
+                let y = b0 * *x + b1 * *x1 + b2 * *x2 - a1 * *y1 - a2 * *y2;
+                (*x2, *x1) = (*x1, *x);
+                (*y2, *y1) = (*y1, y);
+                *x = y;
+            }
+        }
+    }
+}
+
+/// Echoes, spaced a number of beats apart at the current tempo.
+pub struct Delay {
+    rate: f32,
+    params: [Param; 3],
+    bpm: Bpm,
+    /// Interleaved history, as a ring of frames.
+    buffer: Vec<f32>,
+    frames: usize,
+    write: usize,
+    delay: usize,
+}
+
+impl Delay {
+    const BEATS: usize = 0;
+    const FEEDBACK: usize = 1;
+    const MIX: usize = 2;
+
+    /// Longest delay, in beats.
+    const MAX_BEATS: f64 = 4.0;
+
+    pub fn new(rate: u32, bpm: Bpm) -> Self {
+        // Room for the longest delay at the slowest tempo, so the echo always lands on the beat.
+        let max_time = Self::MAX_BEATS * 60.0 / Bpm::MIN;
+        let frames = (max_time * rate as f64) as usize + 2;
+        let mut delay = Self {
+            rate: rate as f32,
+            params: [
+                Param::new("beats", 0.125, Self::MAX_BEATS, 0.75),
+                Param::new("feedback", 0.0, 0.95, 0.4),
+                Param::new("mix", 0.0, 1.0, 1.0),
+            ],
+            bpm,
+            buffer: Vec::new(),
+            frames,
+            write: 0,
+            delay: 1,
+        };
+        delay.update_params();
+        delay
+    }
+}
+
+impl Effect for Delay {
+    fn name(&self) -> &'static str {
+        "delay"
+    }
+
+    fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut [Param] {
+        &mut self.params
+    }
+
+    fn update_params(&mut self) {
+        let seconds = self.params[Self::BEATS].value * 60.0 / self.bpm.as_f64();
+        self.delay = ((seconds as f32 * self.rate) as usize).clamp(1, self.frames - 1);
+    }
+
+    fn set_tempo(&mut self, bpm: Bpm) {
+        if bpm != self.bpm {
+            self.bpm = bpm;
+            self.update_params();
+        }
+    }
+
+    fn process(&mut self, frames: &mut [f32], channels: usize) {
+        self.buffer.resize(self.frames * channels, 0.0);
+        let feedback = self.params[Self::FEEDBACK].value as f32;
+        let mix = self.params[Self::MIX].value as f32;
+
+        for frame in frames.chunks_mut(channels) {
+            let read = (self.write + self.frames - self.delay) % self.frames;
+            for (ch, x) in frame.iter_mut().enumerate() {
+                let echo = self.buffer[read * channels + ch];
+                self.buffer[self.write * channels + ch] = *x + feedback * echo;
+                *x = (1.0 - mix) * *x + mix * echo;
+            }
+            self.write = (self.write + 1) % self.frames;
+        }
+    }
+}
+
+/// Feedback comb filter with a low pass in the loop, for the reverb.
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filtered: f32,
+}
+
+impl Comb {
+    fn process(&mut self, x: f32, feedback: f32, damp: f32) -> f32 {
+        let y = self.buffer[self.index];
+        self.filtered = y * (1.0 - damp) + self.filtered * damp;
+        self.buffer[self.index] = x + self.filtered * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        y
+    }
+}
+
+/// Schroeder all-pass, to thicken the comb echoes.
+struct AllPass {
+    buffer: Vec<f32>,
+    index: usize,
+}
+
+impl AllPass {
+    const GAIN: f32 = 0.5;
+
+    fn process(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        let y = delayed - x;
+        self.buffer[self.index] = x + delayed * Self::GAIN;
+        self.index = (self.index + 1) % self.buffer.len();
+        y
+    }
+}
+
+/// A small Schroeder reverb, on the mono sum of the channels.
+pub struct Reverb {
+    params: [Param; 3],
+    combs: Vec<Comb>,
+    all_passes: Vec<AllPass>,
+}
+
+impl Reverb {
+    const SIZE: usize = 0;
+    const DAMP: usize = 1;
+    const MIX: usize = 2;
+
+    /// Delay lengths in samples at 44.1 kHz, from Freeverb.
+    const COMBS: [usize; 4] = [1116, 1188, 1277, 1356];
+    const ALL_PASSES: [usize; 2] = [556, 441];
+
+    pub fn new(rate: u32) -> Self {
+        let scale = |length: usize| (length * rate as usize / 44100).max(1);
+        Self {
+            params: [
+                Param::new("size", 0.0, 0.98, 0.84),
+                Param::new("damp", 0.0, 1.0, 0.2),
+                Param::new("mix", 0.0, 1.0, 1.0),
+            ],
+            combs: Self::COMBS
+                .iter()
+                .map(|&length| Comb {
+                    buffer: vec![0.0; scale(length)],
+                    index: 0,
+                    filtered: 0.0,
+                })
+                .collect(),
+            all_passes: Self::ALL_PASSES
+                .iter()
+                .map(|&length| AllPass {
+                    buffer: vec![0.0; scale(length)],
+                    index: 0,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Effect for Reverb {
+    fn name(&self) -> &'static str {
+        "reverb"
+    }
+
+    fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut [Param] {
+        &mut self.params
+    }
+
+    fn process(&mut self, frames: &mut [f32], channels: usize) {
+        let feedback = self.params[Self::SIZE].value as f32;
+        let damp = self.params[Self::DAMP].value as f32;
+        let mix = self.params[Self::MIX].value as f32;
+        // Keep the combs from summing far past full scale.
+        let input_gain = 0.015 * 4.0 / channels as f32;
+
+        for frame in frames.chunks_mut(channels) {
+            let input = frame.iter().sum::<f32>() * input_gain;
+            let mut wet: f32 = self
+                .combs
+                .iter_mut()
+                .map(|comb| comb.process(input, feedback, damp))
+                .sum();
+            for all_pass in &mut self.all_passes {
+                wet = all_pass.process(wet);
+            }
+            for x in frame.iter_mut() {
+                *x = (1.0 - mix) * *x + mix * wet;
+            }
+        }
+    }
+}
+
+/// Evens out the level of the mix, by turning down anything over the threshold.
+pub struct Compressor {
+    rate: f32,
+    params: [Param; 5],
+    /// Level being followed, in dB.
+    envelope: f32,
+}
+
+impl Compressor {
+    const THRESHOLD: usize = 0;
+    const RATIO: usize = 1;
+    const ATTACK: usize = 2;
+    const RELEASE: usize = 3;
+    const MAKEUP: usize = 4;
+
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: rate as f32,
+            params: [
+                Param::new("threshold", -60.0, 0.0, -12.0),
+                Param::new("ratio", 1.0, 20.0, 1.0),
+                Param::new("attack", 0.1, 100.0, 5.0),
+                Param::new("release", 10.0, 1000.0, 100.0),
+                Param::new("makeup", 0.0, 24.0, 0.0),
+            ],
+            envelope: -120.0,
+        }
+    }
+
+    /// Smoothing coefficient for a time constant in milliseconds.
+    fn coefficient(&self, ms: f64) -> f32 {
+        (-1.0 / (ms as f32 * 0.001 * self.rate)).exp()
+    }
+}
+
+impl Effect for Compressor {
+    fn name(&self) -> &'static str {
+        "compressor"
+    }
+
+    fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    fn params_mut(&mut self) -> &mut [Param] {
+        &mut self.params
+    }
+
+    fn process(&mut self, frames: &mut [f32], channels: usize) {
+        let threshold = self.params[Self::THRESHOLD].value as f32;
+        let ratio = self.params[Self::RATIO].value as f32;
+        let attack = self.coefficient(self.params[Self::ATTACK].value);
+        let release = self.coefficient(self.params[Self::RELEASE].value);
+        let makeup = self.params[Self::MAKEUP].value as f32;
+
+        for frame in frames.chunks_mut(channels) {
+            let peak = frame.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+            let level = 20.0 * peak.max(1e-6).log10();
+            let coefficient = if level > self.envelope {
+                attack
+            } else {
+                release
+            };
+            self.envelope = coefficient * self.envelope + (1.0 - coefficient) * level;
+
+            let over = (self.envelope - threshold).max(0.0);
+            let gain_db = makeup - over * (1.0 - 1.0 / ratio);
+            let gain = 10.0f32.powf(gain_db / 20.0);
+            for x in frame.iter_mut() {
+                *x *= gain;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 44100;
+
+    /// Run a mono effect over a constant input, and return the last sample.
+    fn settle(effect: &mut dyn Effect, value: f32) -> f32 {
+        let mut frames = vec![value; RATE as usize];
+        effect.process(&mut frames, 1);
+        frames[frames.len() - 1]
+    }
+
+    #[test]
+    fn filters_pass_or_block_dc() {
+        let mut low = Biquad::new(FilterKind::LowPass, RATE, 1000.0);
+        assert!((settle(&mut low, 0.5) - 0.5).abs() < 1e-4);
+
+        let mut high = Biquad::new(FilterKind::HighPass, RATE, 1000.0);
+        assert!(settle(&mut high, 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn open_low_pass_is_neutral() {
+        let mut filter = Biquad::new(FilterKind::LowPass, RATE, f64::INFINITY);
+        let mut frames: Vec<f32> = (0..1000)
+            .map(|i| if i % 2 == 0 { 0.5 } else { -0.5 })
+            .collect();
+        let input = frames.clone();
+        filter.process(&mut frames, 1);
+        assert_eq!(frames, input);
+    }
+
+    #[test]
+    fn delay_echoes_on_the_beat_and_decays() {
+        let bpm = Bpm::try_from(120).unwrap();
+        let mut delay = Delay::new(RATE, bpm);
+        let beats = delay.get("beats").unwrap();
+        let feedback = delay.get("feedback").unwrap() as f32;
+        let spacing = (beats * 60.0 / bpm.as_f64() * RATE as f64) as usize;
+
+        let mut frames = vec![0.0; spacing * 3 + 1];
+        frames[0] = 1.0;
+        delay.process(&mut frames, 1);
+
+        let echoes: Vec<(usize, f32)> = frames
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.abs() > 1e-6)
+            .map(|(i, &x)| (i, x))
+            .collect();
+        assert_eq!(
+            echoes,
+            [
+                (spacing, 1.0),
+                (spacing * 2, feedback),
+                (spacing * 3, feedback * feedback)
+            ]
+        );
+    }
+
+    #[test]
+    fn longest_delay_at_the_slowest_tempo_stays_on_the_beat() {
+        let mut delay = Delay::new(RATE, Bpm::try_from(40).unwrap());
+        delay.set("beats", 4.0);
+        delay.set("feedback", 0.0);
+        let spacing = 6 * RATE as usize;
+
+        let mut frames = vec![0.0; spacing + 1];
+        frames[0] = 1.0;
+        delay.process(&mut frames, 1);
+        assert_eq!(frames[spacing], 1.0);
+        assert_eq!(frames.iter().filter(|x| **x != 0.0).count(), 1);
+    }
+
+    #[test]
+    fn compressor_turns_down_only_over_the_threshold() {
+        let mut compressor = Compressor::new(RATE);
+        assert!((settle(&mut compressor, 1.0) - 1.0).abs() < 1e-4);
+
+        compressor.set("ratio", 4.0);
+        // 0 dB is 12 dB over the threshold, so comes out 3 dB over it.
+        let expected = 10.0f32.powf(-9.0 / 20.0);
+        assert!((settle(&mut compressor, 1.0) - expected).abs() < 1e-3);
+
+        // 20 dB under the threshold, once the envelope has released.
+        assert!((settle(&mut compressor, 0.1) - 0.1).abs() < 1e-4);
+    }
+
+    #[test]
+    fn parallel_chain_sums_each_effect_on_the_same_input() {
+        let mut chain = Chain::parallel(vec![
+            Box::new(Biquad::new(FilterKind::LowPass, RATE, 1000.0)),
+            Box::new(Biquad::new(FilterKind::HighPass, RATE, 1000.0)),
+        ]);
+        for _ in 0..2 {
+            let mut frames = vec![0.5; RATE as usize * 2];
+            chain.process(&mut frames, 2);
+            // The low pass lets DC through and the high pass blocks it.
+            assert!((frames[frames.len() - 1] - 0.5).abs() < 1e-4);
+        }
+    }
+}
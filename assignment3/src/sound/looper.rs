@@ -0,0 +1,306 @@
+/**
+ * A long loop sample that plays under the drums, in time with the score.
+ *
+ * The loop covers a whole number of bars, and is started again from the top every time those bars come
+ * around, so it can't drift from the score.
+ */
+use std::{fmt, io, str::FromStr};
+
+use crate::{
+    sound::{
+        Beat,
+        samples::{Sample, Source},
+    },
+    units::Bpm,
+};
+
+/// How the loop keeps to the tempo.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Sped up or slowed down to fill its bars exactly. The pitch follows the tempo, like a turntable.
+    Stretch,
+    /// Played at its own speed and cut off, or followed by silence, until its bars come around again.
+    Retrigger,
+}
+
+impl fmt::Display for LoopMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stretch => write!(f, "stretch"),
+            Self::Retrigger => write!(f, "retrigger"),
+        }
+    }
+}
+
+impl FromStr for LoopMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stretch" => Ok(Self::Stretch),
+            "retrigger" => Ok(Self::Retrigger),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopState {
+    Stopped,
+    /// Starts on the next bar line.
+    Waiting,
+    Playing,
+}
+
+impl fmt::Display for LoopState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stopped => write!(f, "stopped"),
+            Self::Waiting => write!(f, "waiting"),
+            Self::Playing => write!(f, "playing"),
+        }
+    }
+}
+
+pub struct Loop {
+    sample: Sample,
+    bars: u32,
+    mode: LoopMode,
+    state: LoopState,
+    muted: bool,
+
+    source: Option<Source>,
+    /// Position in the sample, in frames.
+    pos: f64,
+    /// Bar lines since the loop started from the top.
+    bar: u32,
+    /// Beats in the bar that is playing.
+    bar_length: Beat,
+}
+
+impl Loop {
+    /// A stopped loop of a sample that covers `bars` bars.
+    pub fn new(sample: Sample, bars: u32, mode: LoopMode) -> Self {
+        Self {
+            sample,
+            bars: bars.max(1),
+            mode,
+            state: LoopState::Stopped,
+            muted: false,
+            source: None,
+            pos: 0.0,
+            bar: 0,
+            bar_length: 4.0,
+        }
+    }
+
+    /// Start from the top on the next bar line.
+    pub fn start(&mut self) {
+        if self.state == LoopState::Stopped {
+            self.state = LoopState::Waiting;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.state = LoopState::Stopped;
+        self.source = None;
+    }
+
+    pub fn state(&self) -> LoopState {
+        self.state
+    }
+
+    /// Silence the loop, while it keeps its place.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_mode(&mut self, mode: LoopMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> LoopMode {
+        self.mode
+    }
+
+    /// Called on every bar line of the score, with the length of the bar that starts there.
+    /// Fails if the sample can't be read when it starts again.
+    pub fn bar(&mut self, bar_length: Beat) -> io::Result<()> {
+        self.bar_length = bar_length;
+        match self.state {
+            LoopState::Stopped => return Ok(()),
+            LoopState::Waiting => {
+                self.state = LoopState::Playing;
+                self.bar = 0;
+            }
+            LoopState::Playing => self.bar = (self.bar + 1) % self.bars,
+        }
+
+        if self.bar == 0 {
+            self.pos = 0.0;
+            // Let go of the old stream first, so a read error leaves the loop silent rather than stale.
+            self.source = None;
+            self.source = Some(self.sample.source()?);
+        }
+        Ok(())
+    }
+
+    /// Add the next frames of the loop to `out`, as interleaved samples in [-1.0, 1.0].
+    pub fn render(&mut self, out: &mut [f32], channels: usize, rate: u32, bpm: Bpm) {
+        let Some(source) = &mut self.source else {
+            return;
+        };
+
+        let frames = source.len() / channels;
+        // Frames of the sample to move per frame of output.
+        let speed = match self.mode {
+            LoopMode::Stretch => {
+                let seconds = self.bars as f64 * self.bar_length * 60.0 / f64::from(bpm);
+                frames as f64 / (seconds * rate as f64)
+            }
+            LoopMode::Retrigger => 1.0,
+        };
+        let gain = if self.muted { 0.0 } else { 1.0 };
+
+        for frame in out.chunks_exact_mut(channels) {
+            let index = self.pos as usize;
+            if index >= frames {
+                break;
+            }
+            // Wait for a stream that hasn't read this far yet, in silence.
+            if !source.has((index + 2) * channels) {
+                continue;
+            }
+            let fraction = (self.pos - index as f64) as f32;
+
+            for (ch, out) in frame.iter_mut().enumerate() {
+                let si = index * channels + ch;
+                let current = source.get(si).unwrap_or(0) as f32;
+                let next = source.get(si + channels).unwrap_or(0) as f32;
+                *out += (current + (next - current) * fraction) / 32768.0 * gain;
+            }
+
+            self.pos += speed;
+        }
+        source.forget(self.pos as usize * channels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+    use crate::sound::samples::SampleStore;
+
+    /// A slow output rate, so a bar at 60 BPM is a handful of frames.
+    const RATE: u32 = 8;
+
+    /// Write the samples to a WAV file, and add it to the store.
+    fn sample(store: &SampleStore, samples: &[i16]) -> Sample {
+        // Tests run in parallel, so every file gets its own name.
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "beat_box_loop_test_{}_{}.wav",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for &s in samples {
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let sample = store.add(&path).unwrap();
+        // Loaded into memory now, so the file can go.
+        sample.source().unwrap();
+        fs::remove_file(&path).unwrap();
+        sample
+    }
+
+    fn looper(samples: &[i16], bars: u32, mode: LoopMode) -> Loop {
+        let store = SampleStore::new(1 << 20);
+        Loop::new(sample(&store, samples), bars, mode)
+    }
+
+    /// Render mono frames at 60 BPM.
+    fn render(looper: &mut Loop, frames: usize) -> Vec<i16> {
+        let mut out = vec![0.0; frames];
+        looper.render(&mut out, 1, RATE, Bpm::try_from(60).unwrap());
+        out.iter().map(|x| (x * 32768.0).round() as i16).collect()
+    }
+
+    #[test]
+    fn waiting_loop_starts_on_the_next_bar() {
+        let mut looper = looper(&[100, 200, 300], 1, LoopMode::Retrigger);
+        looper.start();
+        assert_eq!(looper.state(), LoopState::Waiting);
+        assert_eq!(render(&mut looper, 4), [0; 4]);
+
+        looper.bar(4.0).unwrap();
+        assert_eq!(looper.state(), LoopState::Playing);
+        assert_eq!(render(&mut looper, 4), [100, 200, 300, 0]);
+    }
+
+    #[test]
+    fn restarts_every_bars_bar_lines() {
+        let samples: Vec<i16> = (1..=64).collect();
+        let mut looper = looper(&samples, 2, LoopMode::Retrigger);
+        looper.start();
+        looper.bar(4.0).unwrap();
+        assert_eq!(render(&mut looper, 2), [1, 2]);
+
+        // Half way through, so it carries on.
+        looper.bar(4.0).unwrap();
+        assert_eq!(render(&mut looper, 2), [3, 4]);
+
+        // Back to the top.
+        looper.bar(4.0).unwrap();
+        assert_eq!(render(&mut looper, 2), [1, 2]);
+    }
+
+    #[test]
+    fn stretch_fills_its_bars_exactly() {
+        let mut looper = looper(&[1000; 16], 2, LoopMode::Stretch);
+        looper.start();
+        looper.bar(3.0).unwrap();
+
+        // 2 bars of 3 beats at 60 BPM is 6 seconds, 48 frames, so the sample plays at a third of its speed.
+        let out = render(&mut looper, 56);
+        assert!(out[..48].iter().all(|&x| x > 0), "{:?}", out);
+        assert_eq!(out[48..], [0; 8]);
+    }
+
+    #[test]
+    fn retrigger_plays_at_its_own_speed() {
+        let mut looper = looper(&[100, 200, 300, 400], 2, LoopMode::Retrigger);
+        looper.start();
+        looper.bar(3.0).unwrap();
+        assert_eq!(render(&mut looper, 6), [100, 200, 300, 400, 0, 0]);
+    }
+
+    #[test]
+    fn muting_keeps_the_place() {
+        let mut looper = looper(&[100, 200, 300, 400], 1, LoopMode::Retrigger);
+        looper.start();
+        looper.bar(4.0).unwrap();
+
+        looper.set_muted(true);
+        assert_eq!(render(&mut looper, 2), [0, 0]);
+        looper.set_muted(false);
+        assert_eq!(render(&mut looper, 2), [300, 400]);
+    }
+}
@@ -1,9 +1,16 @@
-use std::rc::Rc;
-
+pub mod effects;
+pub mod looper;
+pub mod pattern;
 pub mod playback;
+pub mod samples;
 pub mod score;
+pub mod sequencer;
+pub mod song;
+
+pub type Beat = f64;
 
-type Beat = f64;
+/// Index into the step grid of a score.
+pub type Step = usize;
 
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum Instrument {
@@ -34,24 +41,35 @@ impl Instrument {
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct NoteEvent {
     pub instrument: Instrument,
+    /// MIDI style velocity in [0, 127].
+    pub velocity: u8,
+    /// Shift from the instrument's tuning, in cents.
+    pub pitch: i32,
 }
 
-pub fn load_wav_mono_i16(path: &str) -> Rc<[i16]> {
-    let reader = hound::WavReader::open(path).expect("Failed to open WAV file");
-    let spec = reader.spec();
+impl NoteEvent {
+    /// Velocity of notes from the score, which play at full gain.
+    pub const DEFAULT_VELOCITY: u8 = 100;
 
-    assert_eq!(
-        spec.sample_format,
-        hound::SampleFormat::Int,
-        "Only integer WAV supported"
-    );
-    assert_eq!(spec.bits_per_sample, 16, "Only 16-bit WAV supported");
-    assert_eq!(spec.channels, 1, "Only mono WAV supported.");
+    /// Furthest a note can be shifted either way, in cents. Two octaves.
+    pub const MAX_PITCH: i32 = 2400;
 
-    let samples: Vec<i16> = reader
-        .into_samples::<i16>()
-        .map(|s| s.expect("Error reading sample"))
-        .collect();
+    /// A note at the default velocity.
+    pub fn new(instrument: Instrument) -> Self {
+        Self {
+            instrument,
+            velocity: Self::DEFAULT_VELOCITY,
+            pitch: 0,
+        }
+    }
 
-    Rc::from(samples.into_boxed_slice())
+    /// The same note, shifted by `pitch` cents.
+    pub fn with_pitch(self, pitch: i32) -> Self {
+        Self { pitch, ..self }
+    }
+
+    /// Velocity as a gain in [0.0, 1.0]. The default velocity and above play at full gain.
+    pub fn gain(self) -> f32 {
+        (self.velocity as f32 / Self::DEFAULT_VELOCITY as f32).min(1.0)
+    }
 }
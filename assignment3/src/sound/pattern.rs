@@ -0,0 +1,201 @@
+/**
+ * Named patterns saved from the step sequencer, kept as text files in a directory.
+ *
+ * File format, one setting per line:
+ * ```text
+ * mode 1
+ * signature 4/4
+ * steps_per_beat 2
+ * bars 2
+ * track 0 0 2 4 6
+ * track 2:-500 3 7
+ * ```
+ * Each `track` line is the instrument index, with an optional pitch in cents after a ':',
+ * followed by the steps with a note. Pitches past two octaves are clamped to them.
+ */
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::sound::{
+    Instrument, NoteEvent,
+    score::{Score, ScoreType, TimeSignature, Track},
+};
+
+pub struct PatternLibrary {
+    dir: PathBuf,
+    patterns: HashMap<String, Score>,
+}
+
+impl PatternLibrary {
+    const EXTENSION: &str = "pat";
+
+    /// Load every pattern in the directory. A missing directory is an empty library,
+    /// and files that can't be read or parsed are skipped with a warning.
+    pub fn load<P: Into<PathBuf>>(dir: P) -> Self {
+        let dir = dir.into();
+        let mut patterns = HashMap::new();
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!(
+                        "Warning: could not read pattern directory {}: {}",
+                        dir.display(),
+                        e
+                    );
+                }
+                return Self { dir, patterns };
+            }
+        };
+
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    eprintln!(
+                        "Warning: could not read pattern directory {}: {}",
+                        dir.display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            if path.extension().is_none_or(|ext| ext != Self::EXTENSION) {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    eprintln!("Warning: could not read pattern {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            match parse(&text) {
+                Some(score) => {
+                    patterns.insert(name.to_owned(), score);
+                }
+                None => eprintln!("Warning: could not parse pattern {}", path.display()),
+            }
+        }
+
+        Self { dir, patterns }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Score> {
+        self.patterns.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.patterns.keys().map(String::as_str)
+    }
+
+    /// Save a score under a name, and write it to disk.
+    pub fn save(&mut self, name: &str, score: &Score) -> io::Result<()> {
+        if !is_valid_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Pattern names may only use letters, digits, '-' and '_'.",
+            ));
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        fs::write(self.path(name), format(score))?;
+        // Keep a copy that starts from the top, not wherever the playhead was.
+        let copy = Score::new(
+            score.t,
+            score.signature(),
+            score.steps_per_beat(),
+            score.bars(),
+            score.tracks().to_vec(),
+        );
+        self.patterns.insert(name.to_owned(), copy);
+
+        Ok(())
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        Path::new(&self.dir).join(format!("{}.{}", name, Self::EXTENSION))
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn format(score: &Score) -> String {
+    let mut text = String::new();
+    writeln!(text, "mode {}", score.t.to_index()).unwrap();
+    writeln!(text, "signature {}", score.signature()).unwrap();
+    writeln!(text, "steps_per_beat {}", score.steps_per_beat()).unwrap();
+    writeln!(text, "bars {}", score.bars()).unwrap();
+    for track in score.tracks() {
+        write!(text, "track {}", track.instrument().to_index()).unwrap();
+        if track.pitch() != 0 {
+            write!(text, ":{}", track.pitch()).unwrap();
+        }
+        for step in track.steps() {
+            write!(text, " {}", step).unwrap();
+        }
+        writeln!(text).unwrap();
+    }
+    text
+}
+
+fn parse(text: &str) -> Option<Score> {
+    let mut t = ScoreType::Empty;
+    let mut signature = TimeSignature::COMMON;
+    let mut steps_per_beat = 2;
+    let mut bars = 1;
+    let mut tracks = Vec::new();
+
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("mode") => t = ScoreType::from_index(parts.next()?.parse().ok()?),
+            Some("signature") => {
+                let (beats, unit) = parts.next()?.split_once('/')?;
+                signature = TimeSignature::new(beats.parse().ok()?, unit.parse().ok()?);
+            }
+            Some("steps_per_beat") => steps_per_beat = parts.next()?.parse().ok()?,
+            Some("bars") => bars = parts.next()?.parse().ok()?,
+            Some("track") => {
+                let instrument = parts.next()?;
+                let (instrument, pitch) = match instrument.split_once(':') {
+                    Some((instrument, pitch)) => (
+                        instrument,
+                        pitch
+                            .parse::<i32>()
+                            .ok()?
+                            .clamp(-NoteEvent::MAX_PITCH, NoteEvent::MAX_PITCH),
+                    ),
+                    None => (instrument, 0),
+                };
+                let instrument = Instrument::from_index(instrument.parse().ok()?);
+                let mut steps = parts.map(|p| p.parse().ok()).collect::<Option<Vec<_>>>()?;
+                steps.sort_unstable();
+                steps.dedup();
+                tracks.push(Track::pitched(instrument, pitch, steps));
+            }
+            Some(_) => return None,
+            None => {}
+        }
+    }
+
+    if signature.beats_per_bar == 0 || steps_per_beat == 0 {
+        return None;
+    }
+
+    Some(Score::new(t, signature, steps_per_beat, bars, tracks))
+}
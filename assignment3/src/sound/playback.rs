@@ -1,65 +1,222 @@
-use std::{collections::HashMap, hash::Hash, rc::Rc};
+use std::{collections::HashMap, hash::Hash, io};
 
 use alsa::{PCM, pcm};
 
-use crate::units::Volume;
+use crate::{
+    sound::{
+        effects::{Bus, Chain},
+        looper::Loop,
+        samples::{Sample, Source},
+    },
+    units::{Bpm, Volume},
+};
 
 pub struct PlayingSound<H> {
-    pos: usize,
+    /// The sound's samples, kept for as long as it plays even if the file is reloaded.
+    source: Source,
+    /// Position in frames, fractional when the pitch is shifted.
+    pos: f64,
     handle: H,
+    gain: f32,
+    /// Pitch of this note, in cents from the instrument's tuning.
+    pitch: i32,
 }
 
-pub struct Playback<'a, H> {
-    instruments: HashMap<H, Rc<[i16]>>,
+/// Where the mixed audio goes.
+pub trait Sink {
+    type Error;
 
-    playing: Vec<PlayingSound<H>>,
+    /// Frames that can be written now, without queueing too far ahead.
+    fn avail(&mut self) -> Result<usize, Self::Error>;
 
-    io: pcm::IO<'a, i16>,
+    /// Write interleaved frames.
+    fn write(&mut self, samples: &[i16]) -> Result<(), Self::Error>;
+}
 
-    channels: u32,
-    transfer_size: usize,
+/// An ALSA playback device.
+pub struct AlsaSink<'a> {
+    pcm: &'a PCM,
+    io: pcm::IO<'a, i16>,
+    /// Frames queued in the device, past which nothing more is written.
     buffer_size: usize,
 }
 
-impl<'a, H: Hash + Eq> Playback<'a, H> {
-    pub fn new(
-        pcm: &'a PCM,
-        channels: u32,
-        rate: i32,
-        transfer_size: usize,
-        buffer_size: usize,
-    ) -> alsa::Result<Self> {
+impl<'a> AlsaSink<'a> {
+    pub fn new(pcm: &'a PCM, channels: u32, rate: u32, buffer_size: usize) -> alsa::Result<Self> {
         let io = {
             use alsa::pcm::{Access, Format, HwParams};
             // --- Setup ALSA playback device ---
             let hwp = HwParams::any(pcm)?;
             hwp.set_channels(channels)?;
-            hwp.set_rate(rate as u32, alsa::ValueOr::Nearest)?;
+            hwp.set_rate(rate, alsa::ValueOr::Nearest)?;
             hwp.set_format(Format::s16())?;
             hwp.set_access(Access::RWInterleaved)?;
             pcm.hw_params(&hwp)?;
             pcm.io_i16()?
         };
 
+        Ok(Self {
+            pcm,
+            io,
+            buffer_size,
+        })
+    }
+}
+
+impl Sink for AlsaSink<'_> {
+    type Error = alsa::Error;
+
+    fn avail(&mut self) -> alsa::Result<usize> {
+        let status = self.pcm.status()?;
+
+        // Make sure not to fill past buffer_size
+        if status.get_delay() as usize > self.buffer_size {
+            return Ok(0);
+        }
+        Ok(status.get_avail() as usize)
+    }
+
+    fn write(&mut self, samples: &[i16]) -> alsa::Result<()> {
+        self.io.writei(samples).map(|_| ())
+    }
+}
+
+pub struct Playback<H, S> {
+    instruments: HashMap<H, Sample>,
+
+    playing: Vec<PlayingSound<H>>,
+
+    sink: S,
+
+    channels: u32,
+    rate: u32,
+    transfer_size: usize,
+
+    /// Level each instrument sends to the send bus, in [0.0, 1.0].
+    sends: HashMap<H, f32>,
+    /// Each instrument's tuning, as semitones and cents.
+    tuning: HashMap<H, (i32, i32)>,
+    /// Live pitch bend on every sound, in cents.
+    bend: f64,
+    master: Chain,
+    send: Chain,
+
+    /// Backing loop, played dry under the instruments.
+    looper: Option<Loop>,
+    bpm: Bpm,
+}
+
+impl<H: Hash + Eq, S: Sink> Playback<H, S> {
+    /// Mix into `sink`, at most `transfer_size` frames at a time.
+    pub fn new(sink: S, channels: u32, rate: u32, transfer_size: usize) -> Self {
         let instruments = HashMap::new();
         let playing = Vec::new();
 
-        Ok(Playback {
+        Playback {
             instruments,
             playing,
-            io,
+            sink,
             channels,
+            rate,
             transfer_size,
-            buffer_size,
-        })
+            sends: HashMap::new(),
+            tuning: HashMap::new(),
+            bend: 0.0,
+            master: Chain::serial(Vec::new()),
+            send: Chain::parallel(Vec::new()),
+            looper: None,
+            bpm: Bpm::try_from(120).unwrap(),
+        }
+    }
+
+    /// Replace the effects on a bus.
+    pub fn set_chain(&mut self, bus: Bus, chain: Chain) {
+        *self.chain_mut(bus) = chain;
+    }
+
+    pub fn chain(&self, bus: Bus) -> &Chain {
+        match bus {
+            Bus::Master => &self.master,
+            Bus::Send => &self.send,
+        }
+    }
+
+    pub fn chain_mut(&mut self, bus: Bus) -> &mut Chain {
+        match bus {
+            Bus::Master => &mut self.master,
+            Bus::Send => &mut self.send,
+        }
+    }
+
+    /// Set how much of an instrument goes to the send bus, in [0.0, 1.0].
+    pub fn set_send(&mut self, handle: H, level: f32) {
+        self.sends.insert(handle, level.clamp(0.0, 1.0));
+    }
+
+    pub fn send(&self, handle: &H) -> f32 {
+        self.sends.get(handle).copied().unwrap_or(0.0)
+    }
+
+    /// Tune an instrument up or down, in semitones and cents.
+    pub fn set_tuning(&mut self, handle: H, semitones: i32, cents: i32) {
+        self.tuning.insert(handle, (semitones, cents));
     }
 
-    pub fn add_instrument(&mut self, sound: Rc<[i16]>, handle: H) {
+    /// An instrument's tuning, as the semitones and cents it was set to.
+    pub fn tuning(&self, handle: &H) -> (i32, i32) {
+        self.tuning.get(handle).copied().unwrap_or((0, 0))
+    }
+
+    /// Bend the pitch of everything playing, in cents.
+    pub fn set_bend(&mut self, cents: f64) {
+        self.bend = cents;
+    }
+
+    /// Set the backing loop, or remove it.
+    pub fn set_loop(&mut self, looper: Option<Loop>) {
+        self.looper = looper;
+    }
+
+    pub fn looper(&self) -> Option<&Loop> {
+        self.looper.as_ref()
+    }
+
+    pub fn looper_mut(&mut self) -> Option<&mut Loop> {
+        self.looper.as_mut()
+    }
+
+    /// Keep tempo synced effects and the loop in time.
+    pub fn set_tempo(&mut self, bpm: Bpm) {
+        self.bpm = bpm;
+        self.master.set_tempo(bpm);
+        self.send.set_tempo(bpm);
+    }
+
+    pub fn add_instrument(&mut self, sound: Sample, handle: H) {
         self.instruments.insert(handle, sound);
     }
 
-    pub fn start_sound(&mut self, handle: H) {
-        self.playing.push(PlayingSound { pos: 0, handle });
+    pub fn start_sound(&mut self, handle: H) -> io::Result<()> {
+        self.start_sound_with_gain(handle, 1.0)
+    }
+
+    /// Start a sound scaled by `gain` in [0.0, 1.0], such as from a note velocity.
+    pub fn start_sound_with_gain(&mut self, handle: H, gain: f32) -> io::Result<()> {
+        self.start_note(handle, gain, 0)
+    }
+
+    /// Start a sound with a gain, shifted by `pitch` cents from the instrument's tuning.
+    /// Fails if the sample isn't in memory and can't be read.
+    pub fn start_note(&mut self, handle: H, gain: f32, pitch: i32) -> io::Result<()> {
+        let source = self.instruments.get(&handle).expect("Tried to play sound for an instrument that has not been added to the playback system.").source()?;
+        self.playing.push(PlayingSound {
+            source,
+            pos: 0.0,
+            handle,
+            gain,
+            pitch,
+        });
+        Ok(())
     }
 
     pub fn playing_count(&self) -> usize {
@@ -67,50 +224,300 @@ impl<'a, H: Hash + Eq> Playback<'a, H> {
     }
 
     /// Stream small frames of audio
-    pub fn update(&mut self, pcm: &'a PCM, volume: Volume) -> alsa::Result<usize> {
-        let status = pcm.status()?;
-
-        let avail = status.get_avail() as usize;
-        let delay = status.get_delay() as usize;
-
-        // Make sure not to fill past buffer_size
-        if delay > self.buffer_size + self.transfer_size {
-            return Ok(0);
-        }
-
+    pub fn update(&mut self, volume: Volume) -> Result<usize, S::Error> {
         // Determine how many samples need to be written.
-        let frames_to_write = self.transfer_size.min(avail);
+        let frames_to_write = self.transfer_size.min(self.sink.avail()?);
         if frames_to_write == 0 {
             return Ok(0);
         }
 
-        let mut buffer = vec![0i16; frames_to_write * self.channels as usize];
+        let channels = self.channels as usize;
+        let mut dry = vec![0.0f32; frames_to_write * channels];
+        let mut send = vec![0.0f32; frames_to_write * channels];
 
-        // Mix currently playing instruments into buffer
+        // Mix currently playing instruments, and their sends
         self.playing.retain_mut(|p| {
-            let sound = &self.instruments.get(&p.handle).expect("Tried to play sound for an instrument that has not been added to the playback system.");
+            let level = self.sends.get(&p.handle).copied().unwrap_or(0.0);
+            let (semitones, cents) = self.tuning.get(&p.handle).copied().unwrap_or((0, 0));
+            let tuning = semitones * 100 + cents;
+            // Frames of the sound to move per frame of output.
+            let speed = ((tuning + p.pitch) as f64 + self.bend) / 1200.0;
+            let speed = speed.exp2();
+            let sound_frames = p.source.len() / channels;
 
             for frame in 0..frames_to_write {
-                if p.pos >= sound.len() / self.channels as usize {
+                let index = p.pos as usize;
+                if index >= sound_frames {
                     return false; // This sound has finished playing. Remove it from `self.playing`.
                 }
+                // A stream that hasn't read this far yet holds its place and plays silence,
+                // rather than holding up the mix or skipping ahead.
+                if !p.source.has((index + 2) * channels) {
+                    continue;
+                }
+                // Interpolate between the frames either side, towards silence past the end.
+                let fraction = (p.pos - index as f64) as f32;
 
-                for ch in 0..self.channels as usize {
-                    let si = p.pos * self.channels as usize + ch;
-                    let bi = frame * self.channels as usize + ch;
+                for ch in 0..channels {
+                    let si = index * channels + ch;
+                    let bi = frame * channels + ch;
 
-                    buffer[bi] = buffer[bi].saturating_add((sound[si] as f32 * volume.as_scale()).round() as i16);
+                    let current = p.source.get(si).unwrap_or(0) as f32;
+                    let next = p.source.get(si + channels).unwrap_or(0) as f32;
+                    let sample = (current + (next - current) * fraction) / 32768.0 * p.gain;
+                    dry[bi] += sample;
+                    send[bi] += sample * level;
                 }
 
-                p.pos += 1;
+                p.pos += speed;
             }
+            p.source.forget(p.pos as usize * channels);
 
             true
         });
 
-        // Write mixed frames to ALSA
-        self.io.writei(&buffer)?;
+        self.send.process(&mut send, channels);
+        for (dry, wet) in dry.iter_mut().zip(&send) {
+            *dry += wet;
+        }
+        if let Some(looper) = &mut self.looper {
+            looper.render(&mut dry, channels, self.rate, self.bpm);
+        }
+        self.master.process(&mut dry, channels);
+
+        let buffer: Vec<i16> = dry
+            .iter()
+            .map(|sample| {
+                (sample * volume.as_scale() * 32768.0)
+                    .round()
+                    .clamp(-32768.0, 32767.0) as i16
+            })
+            .collect();
+
+        // Write mixed frames to the sink
+        self.sink.write(&buffer)?;
 
         Ok(frames_to_write)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        fs,
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::sound::{Instrument, samples::SampleStore};
+
+    /// Keeps what was written, with room for `avail` frames on each update.
+    struct TestSink {
+        avail: usize,
+        written: Vec<i16>,
+    }
+
+    impl Sink for TestSink {
+        type Error = Infallible;
+
+        fn avail(&mut self) -> Result<usize, Infallible> {
+            Ok(self.avail)
+        }
+
+        fn write(&mut self, samples: &[i16]) -> Result<(), Infallible> {
+            self.written.extend_from_slice(samples);
+            Ok(())
+        }
+    }
+
+    /// Mono playback, 8 frames per update.
+    fn playback(
+        store: &SampleStore,
+        sounds: &[(Instrument, &[i16])],
+    ) -> Playback<Instrument, TestSink> {
+        let sink = TestSink {
+            avail: 64,
+            written: Vec::new(),
+        };
+        let mut playback = Playback::new(sink, 1, 44100, 8);
+        for &(instrument, samples) in sounds {
+            playback.add_instrument(sample(store, samples), instrument);
+        }
+        playback
+    }
+
+    /// Write the samples to a new WAV file.
+    fn write_wav(samples: &[i16]) -> PathBuf {
+        // Tests run in parallel, so every file gets its own name.
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "beat_box_test_{}_{}.wav",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for &s in samples {
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    /// Write the samples to a WAV file, and add it to the store.
+    fn sample(store: &SampleStore, samples: &[i16]) -> Sample {
+        let path = write_wav(samples);
+        let sample = store.add(&path).unwrap();
+        // Loaded into memory now, so the file can go.
+        sample.source().unwrap();
+        fs::remove_file(&path).unwrap();
+        sample
+    }
+
+    fn full() -> Volume {
+        Volume::try_from(100).unwrap()
+    }
+
+    fn mix(playback: &mut Playback<Instrument, TestSink>, volume: Volume) -> Vec<i16> {
+        let frames = playback.update(volume).unwrap();
+        let written = std::mem::take(&mut playback.sink.written);
+        assert_eq!(written.len(), frames);
+        written
+    }
+
+    #[test]
+    fn plays_a_sound_exactly_then_silence() {
+        let store = SampleStore::new(1 << 20);
+        let mut playback = playback(&store, &[(Instrument::Snare, &[1000, -2000, 3000, 4000])]);
+
+        playback.start_sound(Instrument::Snare).unwrap();
+        assert_eq!(
+            mix(&mut playback, full()),
+            [1000, -2000, 3000, 4000, 0, 0, 0, 0]
+        );
+        assert_eq!(playback.playing_count(), 0);
+        assert_eq!(mix(&mut playback, full()), [0; 8]);
+    }
+
+    #[test]
+    fn sums_overlapping_sounds_and_clips() {
+        let store = SampleStore::new(1 << 20);
+        let mut playback = playback(
+            &store,
+            &[
+                (Instrument::HiHat, &[30000, 1000, -30000]),
+                (Instrument::BassDrum, &[30000, -500, -30000]),
+            ],
+        );
+
+        playback.start_sound(Instrument::HiHat).unwrap();
+        playback.start_sound(Instrument::BassDrum).unwrap();
+        assert_eq!(
+            mix(&mut playback, full()),
+            [32767, 500, -32768, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn scales_by_gain_and_volume() {
+        let store = SampleStore::new(1 << 20);
+        let mut playback = playback(&store, &[(Instrument::Snare, &[1000, -2000])]);
+
+        playback
+            .start_sound_with_gain(Instrument::Snare, 0.5)
+            .unwrap();
+        assert_eq!(
+            mix(&mut playback, Volume::try_from(50).unwrap()),
+            [250, -500, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn an_octave_up_plays_every_other_sample() {
+        let store = SampleStore::new(1 << 20);
+        let mut playback = playback(
+            &store,
+            &[(Instrument::Snare, &[100, 200, 300, 400, 500, 600])],
+        );
+
+        playback.set_tuning(Instrument::Snare, 12, 0);
+        playback.start_sound(Instrument::Snare).unwrap();
+        assert_eq!(mix(&mut playback, full()), [100, 300, 500, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn tuning_reads_back_as_it_was_set() {
+        let store = SampleStore::new(1 << 20);
+        let mut playback = playback(&store, &[(Instrument::Snare, &[100, 200, 300])]);
+
+        playback.set_tuning(Instrument::Snare, -3, 50);
+        assert_eq!(playback.tuning(&Instrument::Snare), (-3, 50));
+        assert_eq!(playback.tuning(&Instrument::BassDrum), (0, 0));
+    }
+
+    #[test]
+    fn an_octave_down_interpolates() {
+        let store = SampleStore::new(1 << 20);
+        let mut playback = playback(&store, &[(Instrument::Snare, &[100, 200, 300])]);
+
+        playback.start_note(Instrument::Snare, 1.0, -1200).unwrap();
+        assert_eq!(
+            mix(&mut playback, full()),
+            [100, 150, 200, 250, 300, 150, 0, 0]
+        );
+    }
+
+    #[test]
+    fn a_stream_cut_short_still_ends() {
+        let store = SampleStore::new(1 << 20);
+        store.set_stream_above(10);
+        let path = write_wav(&vec![100; 30000]);
+        // Keep the header, which still claims every sample, and about the first 10000 samples.
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_len(44 + 10000 * 2).unwrap();
+
+        let mut playback = playback(&store, &[]);
+        playback.add_instrument(store.add(&path).unwrap(), Instrument::Snare);
+        playback.start_sound(Instrument::Snare).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while playback.playing_count() > 0 && Instant::now() < deadline {
+            mix(&mut playback, full());
+        }
+        assert_eq!(playback.playing_count(), 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sounds_carry_on_across_updates() {
+        let store = SampleStore::new(1 << 20);
+        let samples: Vec<i16> = (1..=12).collect();
+        let mut playback = playback(&store, &[(Instrument::Snare, &samples)]);
+
+        playback.start_sound(Instrument::Snare).unwrap();
+        assert_eq!(mix(&mut playback, full()), [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(mix(&mut playback, full()), [9, 10, 11, 12, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn writes_no_more_than_the_sink_has_room_for() {
+        let store = SampleStore::new(1 << 20);
+        let mut playback = playback(&store, &[(Instrument::Snare, &[1000, 2000, 3000])]);
+
+        playback.start_sound(Instrument::Snare).unwrap();
+        playback.sink.avail = 0;
+        assert_eq!(mix(&mut playback, full()), []);
+        playback.sink.avail = 2;
+        assert_eq!(mix(&mut playback, full()), [1000, 2000]);
+        playback.sink.avail = 64;
+        assert_eq!(mix(&mut playback, full()), [3000, 0, 0, 0, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,524 @@
+/**
+ * Sample store shared between threads, with a memory budget.
+ *
+ * Short samples are read into memory the first time they play, and the least recently played are dropped
+ * when the budget is passed. Long samples are streamed from disk instead. A changed file is reloaded
+ * into a new buffer, so sounds already playing finish on the old one.
+ */
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak, mpsc},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+type WavReader = hound::WavReader<io::BufReader<fs::File>>;
+
+struct Entry {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    /// Length in samples, from the header.
+    len: usize,
+    buffer: Option<Arc<[i16]>>,
+    /// When the sample last played, in store ticks.
+    used: u64,
+}
+
+struct Store {
+    entries: Vec<Entry>,
+    /// Bytes of samples that can be kept in memory.
+    budget: usize,
+    /// Bytes of samples in memory.
+    loaded: usize,
+    /// Samples longer than this are streamed.
+    stream_above: usize,
+    tick: u64,
+}
+
+impl Store {
+    /// Drop the least recently played buffers, other than `keep`, until the loaded samples fit the budget.
+    fn evict(&mut self, keep: usize) {
+        while self.loaded > self.budget {
+            let Some((index, _)) = self
+                .entries
+                .iter()
+                .enumerate()
+                .filter(|(index, entry)| *index != keep && entry.buffer.is_some())
+                .min_by_key(|(_, entry)| entry.used)
+            else {
+                break;
+            };
+            let buffer = self.entries[index].buffer.take().unwrap();
+            self.loaded -= size_of_val(&buffer[..]);
+        }
+    }
+}
+
+/// Every sample the player can use. Cheap to clone, and every clone shares the same samples.
+#[derive(Clone)]
+pub struct SampleStore {
+    store: Arc<Mutex<Store>>,
+}
+
+impl SampleStore {
+    /// Samples at most 10 seconds long, at 44.1 kHz, are kept in memory.
+    pub const STREAM_ABOVE: usize = 10 * 44100;
+
+    /// Create a store that keeps up to `budget` bytes of samples in memory.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            store: Arc::new(Mutex::new(Store {
+                entries: Vec::new(),
+                budget,
+                loaded: 0,
+                stream_above: Self::STREAM_ABOVE,
+                tick: 0,
+            })),
+        }
+    }
+
+    /// Stream samples longer than this many samples, rather than reading them into memory.
+    pub fn set_stream_above(&self, len: usize) {
+        self.lock().stream_above = len;
+    }
+
+    /// Add a sample file. Only its header is read until it plays.
+    pub fn add<P: AsRef<Path>>(&self, path: P) -> io::Result<Sample> {
+        let path = path.as_ref().to_owned();
+        let modified = fs::metadata(&path)?.modified().ok();
+        let len = open_wav(&path)?.len() as usize;
+
+        let mut store = self.lock();
+        store.entries.push(Entry {
+            path,
+            modified,
+            len,
+            buffer: None,
+            used: 0,
+        });
+        Ok(Sample {
+            store: self.store.clone(),
+            index: store.entries.len() - 1,
+        })
+    }
+
+    /// Bytes of samples in memory.
+    pub fn loaded(&self) -> usize {
+        self.lock().loaded
+    }
+
+    /// Reload every sample whose file changed since it was read. Fails with the first file that can't be read,
+    /// after trying the rest.
+    pub fn reload_changed(&self) -> io::Result<()> {
+        let files: Vec<_> = self
+            .lock()
+            .entries
+            .iter()
+            .map(|entry| (entry.path.clone(), entry.modified, entry.buffer.is_some()))
+            .collect();
+
+        let mut result = Ok(());
+        for (index, (path, modified, loaded)) in files.into_iter().enumerate() {
+            if let Err(e) = self.reload(index, &path, modified, loaded) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// Reload one sample if its file changed. The file is read without holding the lock,
+    /// so sounds can still start while it loads.
+    fn reload(
+        &self,
+        index: usize,
+        path: &Path,
+        modified: Option<SystemTime>,
+        loaded: bool,
+    ) -> io::Result<()> {
+        let now_modified = fs::metadata(path)?.modified().ok();
+        if now_modified == modified {
+            return Ok(());
+        }
+
+        let reader = open_wav(path)?;
+        let len = reader.len() as usize;
+        let buffer = if loaded {
+            Some(read_samples(reader)?)
+        } else {
+            None
+        };
+
+        let mut store = self.lock();
+        let store = &mut *store;
+        let entry = &mut store.entries[index];
+        entry.modified = now_modified;
+        entry.len = len;
+        // Swap in the new buffer. If the sample was loaded from the old file meanwhile, drop it to load again.
+        let old = match buffer {
+            Some(buffer) => {
+                store.loaded += size_of_val(&buffer[..]);
+                entry.buffer.replace(buffer)
+            }
+            None => entry.buffer.take(),
+        };
+        if let Some(old) = old {
+            store.loaded -= size_of_val(&old[..]);
+        }
+        store.evict(index);
+        Ok(())
+    }
+
+    /// Check for changed files every `period`, on a new thread. The thread stops once the store is dropped.
+    pub fn watch(&self, period: Duration) -> thread::JoinHandle<()> {
+        let store = Arc::downgrade(&self.store);
+        thread::spawn(move || {
+            loop {
+                thread::sleep(period);
+                let Some(store) = Weak::upgrade(&store) else {
+                    return;
+                };
+                if let Err(e) = (SampleStore { store }).reload_changed() {
+                    eprintln!("Warning: could not reload sample: {}", e);
+                }
+            }
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store
+            .lock()
+            .expect("Sample store lock must not be poisoned.")
+    }
+}
+
+/// One sample in a store.
+#[derive(Clone)]
+pub struct Sample {
+    store: Arc<Mutex<Store>>,
+    index: usize,
+}
+
+impl Sample {
+    /// Start reading the sample from the beginning, loading it first if it isn't in memory.
+    /// The file is read without holding the lock, like a reload.
+    pub fn source(&self) -> io::Result<Source> {
+        let path = {
+            let mut store = self.lock();
+            store.tick += 1;
+            let tick = store.tick;
+            let stream_above = store.stream_above;
+
+            let entry = &mut store.entries[self.index];
+            entry.used = tick;
+            if let Some(buffer) = &entry.buffer {
+                return Ok(Source::Memory(buffer.clone()));
+            }
+            if entry.len > stream_above {
+                let path = entry.path.clone();
+                drop(store);
+                return Ok(Source::Stream(Stream::open(&path)?));
+            }
+            entry.path.clone()
+        };
+
+        let buffer = read_samples(open_wav(&path)?)?;
+
+        let mut store = self.lock();
+        let store = &mut *store;
+        let entry = &mut store.entries[self.index];
+        // Another sound may have loaded it meanwhile.
+        if let Some(buffer) = &entry.buffer {
+            return Ok(Source::Memory(buffer.clone()));
+        }
+        entry.len = buffer.len();
+        entry.buffer = Some(buffer.clone());
+        store.loaded += size_of_val(&buffer[..]);
+        store.evict(self.index);
+        Ok(Source::Memory(buffer))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Store> {
+        self.store
+            .lock()
+            .expect("Sample store lock must not be poisoned.")
+    }
+}
+
+/// Where a playing sound reads its samples from.
+pub enum Source {
+    Memory(Arc<[i16]>),
+    Stream(Stream),
+}
+
+impl Source {
+    /// Length in samples.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Memory(buffer) => buffer.len(),
+            Self::Stream(stream) => stream.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether every sample before `end` can be read now, or is past the end. A stream may still be reading them.
+    pub fn has(&mut self, end: usize) -> bool {
+        let end = end.min(self.len());
+        end == 0 || self.get(end - 1).is_some()
+    }
+
+    /// The sample at `index`, or None past the end or if the stream hasn't read that far yet.
+    pub fn get(&mut self, index: usize) -> Option<i16> {
+        match self {
+            Self::Memory(buffer) => buffer.get(index).copied(),
+            Self::Stream(stream) => stream.get(index),
+        }
+    }
+
+    /// Samples before `index` won't be read again.
+    pub fn forget(&mut self, index: usize) {
+        if let Self::Stream(stream) = self {
+            stream.forget(index);
+        }
+    }
+}
+
+/// A long sample, read from disk a chunk at a time on its own thread, ahead of the mixer.
+pub struct Stream {
+    chunks: mpsc::Receiver<Vec<i16>>,
+    /// Samples read but not yet forgotten, starting at `start`.
+    buffer: VecDeque<i16>,
+    start: usize,
+    len: usize,
+}
+
+impl Stream {
+    /// Samples read at a time.
+    const CHUNK: usize = 8192;
+    /// Chunks read ahead of the mixer.
+    const AHEAD: usize = 4;
+
+    /// Start reading a file. The first chunk is read before returning, so the sound can start right away.
+    /// The thread stops at the end of the file, or once the stream is dropped.
+    /// A later read error, or a file shorter than its header says, ends the stream where reading stopped.
+    fn open(path: &Path) -> io::Result<Self> {
+        let reader = open_wav(path)?;
+        let len = reader.len() as usize;
+        let mut samples = reader.into_samples::<i16>();
+        let first = samples
+            .by_ref()
+            .take(Self::CHUNK)
+            .collect::<Result<VecDeque<_>, _>>()
+            .map_err(to_io_error)?;
+        let (tx, chunks) = mpsc::sync_channel(Self::AHEAD);
+
+        thread::spawn(move || {
+            loop {
+                let chunk: Result<Vec<_>, _> = samples.by_ref().take(Self::CHUNK).collect();
+                match chunk {
+                    Ok(chunk) if !chunk.is_empty() => {
+                        if tx.send(chunk).is_err() {
+                            return;
+                        }
+                    }
+                    _ => return,
+                }
+            }
+        });
+
+        Ok(Self {
+            chunks,
+            buffer: first,
+            start: 0,
+            len,
+        })
+    }
+
+    fn get(&mut self, index: usize) -> Option<i16> {
+        let offset = index.checked_sub(self.start)?;
+        while offset >= self.buffer.len() {
+            match self.chunks.try_recv() {
+                Ok(chunk) => self.buffer.extend(chunk),
+                Err(mpsc::TryRecvError::Empty) => return None,
+                // The thread has stopped, maybe short of the length in the header, so end at what it read.
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.len = self.start + self.buffer.len();
+                    return None;
+                }
+            }
+        }
+        Some(self.buffer[offset])
+    }
+
+    fn forget(&mut self, index: usize) {
+        let count = index.saturating_sub(self.start).min(self.buffer.len());
+        self.buffer.drain(..count);
+        self.start += count;
+    }
+}
+
+/// Open a WAV file, checking it is mono 16 bit integer.
+fn open_wav(path: &Path) -> io::Result<WavReader> {
+    let reader = hound::WavReader::open(path).map_err(to_io_error)?;
+    let spec = reader.spec();
+    if spec.sample_format != hound::SampleFormat::Int
+        || spec.bits_per_sample != 16
+        || spec.channels != 1
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{}: only mono 16 bit integer WAV is supported",
+                path.display()
+            ),
+        ));
+    }
+    Ok(reader)
+}
+
+fn read_samples(reader: WavReader) -> io::Result<Arc<[i16]>> {
+    reader
+        .into_samples::<i16>()
+        .collect::<Result<_, _>>()
+        .map_err(to_io_error)
+}
+
+fn to_io_error(e: hound::Error) -> io::Error {
+    match e {
+        hound::Error::IoError(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    /// A new WAV file path. Tests run in parallel, so every file gets its own name.
+    fn temp_path() -> PathBuf {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        std::env::temp_dir().join(format!(
+            "beat_box_samples_test_{}_{}.wav",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    fn write_wav(path: &Path, samples: &[i16]) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for &s in samples {
+            writer.write_sample(s).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    /// Add a file of `len` samples, all `value`.
+    fn add(store: &SampleStore, len: usize, value: i16) -> (Sample, PathBuf) {
+        let path = temp_path();
+        write_wav(&path, &vec![value; len]);
+        (store.add(&path).unwrap(), path)
+    }
+
+    fn is_loaded(sample: &Sample) -> bool {
+        sample.lock().entries[sample.index].buffer.is_some()
+    }
+
+    fn first(sample: &Sample) -> Option<i16> {
+        sample.source().unwrap().get(0)
+    }
+
+    #[test]
+    fn loaded_samples_stay_within_the_budget() {
+        // Room for two samples of 100.
+        let store = SampleStore::new(400);
+        let files: Vec<_> = (0..3).map(|i| add(&store, 100, i)).collect();
+        assert_eq!(store.loaded(), 0);
+
+        for (sample, _) in &files {
+            sample.source().unwrap();
+            assert!(store.loaded() <= 400);
+        }
+        assert_eq!(store.loaded(), 400);
+
+        for (_, path) in files {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn least_recently_played_is_dropped_first() {
+        let store = SampleStore::new(400);
+        let files: Vec<_> = (0..3).map(|i| add(&store, 100, i)).collect();
+        let [a, b, c] = [0, 1, 2].map(|i| &files[i].0);
+
+        a.source().unwrap();
+        b.source().unwrap();
+        a.source().unwrap();
+        c.source().unwrap();
+        assert!(is_loaded(a));
+        assert!(!is_loaded(b));
+        assert!(is_loaded(c));
+
+        // Loaded again when it plays, in place of the one played longest ago.
+        assert_eq!(first(b), Some(1));
+        assert!(!is_loaded(a));
+
+        for (_, path) in &files {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn changed_files_are_reloaded_and_playing_sounds_keep_the_old_samples() {
+        let store = SampleStore::new(1 << 20);
+        let (sample, path) = add(&store, 100, 1);
+        let mut playing = sample.source().unwrap();
+
+        write_wav(&path, &[2; 50]);
+        // Make sure the change shows, however coarse the file system's times are.
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        store.reload_changed().unwrap();
+
+        assert_eq!(playing.get(99), Some(1));
+        let source = sample.source().unwrap();
+        assert_eq!(source.len(), 50);
+        assert_eq!(first(&sample), Some(2));
+        assert_eq!(store.loaded(), 100);
+
+        // Nothing changed since.
+        store.reload_changed().unwrap();
+        assert_eq!(store.loaded(), 100);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn streams_can_play_as_soon_as_they_open() {
+        let store = SampleStore::new(1 << 20);
+        store.set_stream_above(10);
+        let (sample, path) = add(&store, Stream::CHUNK * 3, 7);
+
+        let mut source = sample.source().unwrap();
+        assert!(matches!(source, Source::Stream(_)));
+        assert!(source.has(Stream::CHUNK));
+        assert_eq!(source.get(Stream::CHUNK - 1), Some(7));
+        assert_eq!(store.loaded(), 0);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,7 +1,7 @@
 use std::{fmt::Display, time::Instant};
 
 use crate::{
-    sound::{Beat, Instrument, NoteEvent},
+    sound::{Beat, Instrument, NoteEvent, Step},
     units::Bpm,
 };
 
@@ -10,14 +10,16 @@ pub enum ScoreType {
     Empty,
     Standard,
     Funky,
+    Five,
 }
 
 impl ScoreType {
     pub fn from_index(index: usize) -> Self {
-        match usize::strict_rem(index, 3) {
+        match usize::strict_rem(index, 4) {
             0 => ScoreType::Empty,
             1 => ScoreType::Standard,
             2 => ScoreType::Funky,
+            3 => ScoreType::Five,
             _ => unreachable!(),
         }
     }
@@ -27,6 +29,7 @@ impl ScoreType {
             ScoreType::Empty => 0,
             ScoreType::Standard => 1,
             ScoreType::Funky => 2,
+            ScoreType::Five => 3,
         }
     }
 
@@ -35,6 +38,7 @@ impl ScoreType {
             ScoreType::Empty => Score::empty(),
             ScoreType::Standard => Score::standard(),
             ScoreType::Funky => Score::funky(),
+            ScoreType::Five => Score::five(),
         }
     }
 }
@@ -48,19 +52,90 @@ impl Display for ScoreType {
                 ScoreType::Empty => "None",
                 ScoreType::Standard => "Stnd",
                 ScoreType::Funky => "Funk",
+                ScoreType::Five => "Five",
             }
         )
     }
 }
 
+/// Time signature of a score. Beats are counted in `beat_unit` notes, so 7/8 has 7 beats per bar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub beats_per_bar: u32,
+    pub beat_unit: u32,
+}
+
+impl TimeSignature {
+    pub const COMMON: Self = Self::new(4, 4);
+
+    pub const fn new(beats_per_bar: u32, beat_unit: u32) -> Self {
+        Self {
+            beats_per_bar,
+            beat_unit,
+        }
+    }
+}
+
+impl Display for TimeSignature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.beats_per_bar, self.beat_unit)
+    }
+}
+
+/// Playhead position within the score loop. `bar` and `beat` count from 1, `tick` is the step within the beat.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.bar, self.beat, self.tick)
+    }
+}
+
+#[derive(Clone)]
 pub struct Track {
     instrument: Instrument,
-    notes: Vec<Beat>,
+    steps: Vec<Step>,
+    /// Shift from the instrument's tuning, in cents. A score can have a track per pitch, like toms.
+    pitch: i32,
 }
 
+impl Track {
+    pub fn new(instrument: Instrument, steps: Vec<Step>) -> Self {
+        Self::pitched(instrument, 0, steps)
+    }
+
+    pub fn pitched(instrument: Instrument, pitch: i32, steps: Vec<Step>) -> Self {
+        Self {
+            instrument,
+            steps,
+            pitch,
+        }
+    }
+
+    pub fn instrument(&self) -> Instrument {
+        self.instrument
+    }
+
+    pub fn pitch(&self) -> i32 {
+        self.pitch
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+}
+
+#[derive(Clone)]
 pub struct Score {
     tracks: Vec<Track>,
-    length: Beat,
+    signature: TimeSignature,
+    steps_per_beat: u32,
+    bars: u32,
 
     prev: Option<Instant>,
     beat_time: Beat,
@@ -69,10 +144,32 @@ pub struct Score {
 }
 
 impl Score {
+    pub const MAX_BARS: u32 = 16;
+
+    pub fn new(
+        t: ScoreType,
+        signature: TimeSignature,
+        steps_per_beat: u32,
+        bars: u32,
+        tracks: Vec<Track>,
+    ) -> Self {
+        Self {
+            tracks,
+            signature,
+            steps_per_beat,
+            bars: bars.clamp(1, Self::MAX_BARS),
+            prev: None,
+            beat_time: 0.0,
+            t,
+        }
+    }
+
     pub fn empty() -> Self {
         Self {
             tracks: vec![],
-            length: 8.0,
+            signature: TimeSignature::COMMON,
+            steps_per_beat: 2,
+            bars: 2,
             prev: None,
             beat_time: 0.0,
             t: ScoreType::Empty,
@@ -82,22 +179,27 @@ impl Score {
     pub fn standard() -> Self {
         let hihat = Track {
             instrument: Instrument::HiHat,
-            notes: vec![0.0, 2.0, 4.0, 6.0, 8.0, 10.0, 12.0, 14.0],
+            steps: vec![0, 4, 8, 12],
+            pitch: 0,
         };
 
         let snare = Track {
             instrument: Instrument::Snare,
-            notes: vec![4.0, 12.0],
+            steps: vec![8],
+            pitch: 0,
         };
 
         let bassdrum = Track {
             instrument: Instrument::BassDrum,
-            notes: vec![0.0, 8.0],
+            steps: vec![0],
+            pitch: 0,
         };
 
         Self {
             tracks: vec![hihat, snare, bassdrum],
-            length: 8.0,
+            signature: TimeSignature::COMMON,
+            steps_per_beat: 2,
+            bars: 2,
             prev: None,
             beat_time: 0.0,
             t: ScoreType::Standard,
@@ -107,28 +209,64 @@ impl Score {
     pub fn funky() -> Self {
         let hihat = Track {
             instrument: Instrument::HiHat,
-            notes: vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.5, 6.0, 7.0, 7.5],
+            steps: vec![0, 2, 4, 6, 8, 11, 12, 14, 15],
+            pitch: 0,
         };
 
         let snare = Track {
             instrument: Instrument::Snare,
-            notes: vec![2.0, 6.0],
+            steps: vec![4, 12],
+            pitch: 0,
         };
 
         let bassdrum = Track {
             instrument: Instrument::BassDrum,
-            notes: vec![0.0, 3.0, 4.0, 7.0],
+            steps: vec![0, 6, 8, 14],
+            pitch: 0,
         };
 
         Self {
             tracks: vec![hihat, snare, bassdrum],
-            length: 8.0,
+            signature: TimeSignature::COMMON,
+            steps_per_beat: 2,
+            bars: 2,
             prev: None,
             beat_time: 0.0,
             t: ScoreType::Funky,
         }
     }
 
+    /// A 5/4 groove, split 3 + 2.
+    pub fn five() -> Self {
+        let hihat = Track {
+            instrument: Instrument::HiHat,
+            steps: vec![0, 2, 3, 4, 6, 8, 9],
+            pitch: 0,
+        };
+
+        let snare = Track {
+            instrument: Instrument::Snare,
+            steps: vec![4, 8],
+            pitch: 0,
+        };
+
+        let bassdrum = Track {
+            instrument: Instrument::BassDrum,
+            steps: vec![0, 6],
+            pitch: 0,
+        };
+
+        Self {
+            tracks: vec![hihat, snare, bassdrum],
+            signature: TimeSignature::new(5, 4),
+            steps_per_beat: 2,
+            bars: 1,
+            prev: None,
+            beat_time: 0.0,
+            t: ScoreType::Five,
+        }
+    }
+
     pub fn update(&mut self, bpm: Bpm, now: Instant) -> Vec<NoteEvent> {
         let Some(prev) = self.prev else {
             self.prev = Some(now);
@@ -136,32 +274,43 @@ impl Score {
         };
 
         let elapsed: Beat = (now - prev).as_secs_f64() * (f64::from(bpm) / 60.0);
+        self.prev = Some(now);
 
+        self.advance(elapsed)
+    }
+
+    /// Move the playhead forward, returning the notes in [start, end) in the order they are played.
+    pub fn advance(&mut self, elapsed: Beat) -> Vec<NoteEvent> {
         let start: Beat = self.beat_time;
         let end: Beat = self.beat_time + elapsed;
 
-        let offset = (start / self.length).floor() * self.length;
+        // Check every loop of the score that overlaps with [start, end).
+        let length = self.length();
+        let step_count = self.step_count();
+        let steps_per_beat = self.steps_per_beat as Beat;
+        let first_loop = (start / length).floor() as i64;
+        let last_loop = (end / length).floor() as i64;
 
-        let events = self
-            .tracks
-            .iter()
-            .flat_map(|track| {
-                let instrument = track.instrument;
-                track
-                    .notes
-                    .iter()
-                    .filter(|&&time| {
-                        let time = time + offset;
-                        time > start && time < end
-                    })
-                    .map(move |_| NoteEvent { instrument })
+        let mut events: Vec<(Beat, NoteEvent)> = (first_loop..=last_loop)
+            .flat_map(|n| {
+                let offset = n as Beat * length;
+                self.tracks.iter().flat_map(move |track| {
+                    let note = NoteEvent::new(track.instrument).with_pitch(track.pitch);
+                    // Steps past the end are kept, so shortening and then lengthening the score restores them.
+                    track
+                        .steps
+                        .iter()
+                        .filter(move |&&step| step < step_count)
+                        .map(move |&step| (offset + step as Beat / steps_per_beat, note))
+                })
             })
+            .filter(|&(time, _)| time >= start && time < end)
             .collect();
+        events.sort_by(|(a, _), (b, _)| a.total_cmp(b));
 
         self.beat_time = end;
-        self.prev = Some(now);
 
-        events
+        events.into_iter().map(|(_, event)| event).collect()
     }
 
     pub fn get_beat(&self) -> Beat {
@@ -171,4 +320,213 @@ impl Score {
     pub fn set_beat(&mut self, beat: Beat) {
         self.beat_time = beat;
     }
+
+    pub fn signature(&self) -> TimeSignature {
+        self.signature
+    }
+
+    pub fn steps_per_beat(&self) -> u32 {
+        self.steps_per_beat
+    }
+
+    pub fn bars(&self) -> u32 {
+        self.bars
+    }
+
+    /// Total number of steps in one loop of the score.
+    pub fn step_count(&self) -> Step {
+        (self.bars * self.signature.beats_per_bar * self.steps_per_beat) as Step
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    /// Change the number of bars in the score.
+    pub fn set_bars(&mut self, bars: u32) {
+        self.bars = bars.clamp(1, Self::MAX_BARS);
+    }
+
+    /// Toggle a note on the step grid, at the instrument's own pitch.
+    /// Returns whether the note is now set, or None if the step is out of range.
+    pub fn toggle(&mut self, instrument: Instrument, step: Step) -> Option<bool> {
+        if step >= self.step_count() {
+            return None;
+        }
+
+        let track = match self
+            .tracks
+            .iter()
+            .position(|t| t.instrument == instrument && t.pitch == 0)
+        {
+            Some(i) => &mut self.tracks[i],
+            None => {
+                self.tracks.push(Track::new(instrument, Vec::new()));
+                self.tracks.last_mut().unwrap()
+            }
+        };
+
+        match track.steps.binary_search(&step) {
+            Ok(i) => {
+                track.steps.remove(i);
+                Some(false)
+            }
+            Err(i) => {
+                track.steps.insert(i, step);
+                Some(true)
+            }
+        }
+    }
+
+    /// Remove every note for an instrument, at every pitch.
+    pub fn clear(&mut self, instrument: Instrument) {
+        self.tracks.retain(|t| t.instrument != instrument);
+    }
+
+    /// The step grid for one instrument at its own pitch, as 'x' for notes and '.' for rests.
+    pub fn grid(&self, instrument: Instrument) -> String {
+        let steps = self
+            .tracks
+            .iter()
+            .find(|t| t.instrument == instrument && t.pitch == 0)
+            .map(|t| t.steps.as_slice())
+            .unwrap_or_default();
+
+        (0..self.step_count())
+            .map(|step| if steps.contains(&step) { 'x' } else { '.' })
+            .collect()
+    }
+
+    /// Length of one loop of the score, in beats.
+    pub fn length(&self) -> Beat {
+        (self.bars * self.signature.beats_per_bar) as Beat
+    }
+
+    /// Length of one bar, in beats.
+    pub fn bar_length(&self) -> Beat {
+        self.signature.beats_per_bar as Beat
+    }
+
+    /// Beats left until the next bar line. A full bar when sitting exactly on one.
+    pub fn beats_to_bar(&self) -> Beat {
+        let bar_length = self.bar_length();
+        bar_length - self.beat_time.rem_euclid(bar_length)
+    }
+
+    /// Get the current bar:beat:tick of the playhead.
+    pub fn position(&self) -> Position {
+        let steps_per_bar = self.steps_per_beat * self.signature.beats_per_bar;
+        let step =
+            (self.beat_time.rem_euclid(self.length()) * self.steps_per_beat as Beat).floor() as u32;
+
+        Position {
+            bar: step / steps_per_bar + 1,
+            beat: (step % steps_per_bar) / self.steps_per_beat + 1,
+            tick: step % self.steps_per_beat,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn bpm(n: u32) -> Bpm {
+        Bpm::try_from(n).unwrap()
+    }
+
+    /// One bar of 4/4, a step per beat.
+    fn bar(tracks: Vec<Track>) -> Score {
+        Score::new(ScoreType::Empty, TimeSignature::COMMON, 1, 1, tracks)
+    }
+
+    fn instruments(notes: Vec<NoteEvent>) -> Vec<Instrument> {
+        notes.into_iter().map(|note| note.instrument).collect()
+    }
+
+    fn count(notes: &[NoteEvent], instrument: Instrument) -> usize {
+        notes.iter().filter(|n| n.instrument == instrument).count()
+    }
+
+    #[test]
+    fn first_update_only_starts_the_clock() {
+        let mut score = Score::standard();
+        assert!(score.update(bpm(120), Instant::now()).is_empty());
+        assert_eq!(score.get_beat(), 0.0);
+    }
+
+    #[test]
+    fn beat_zero_plays_on_the_first_pass() {
+        let mut score = Score::standard();
+        let start = Instant::now();
+        score.update(bpm(120), start);
+
+        // Half a beat, up to but not including step 1.
+        let notes = score.update(bpm(120), start + Duration::from_millis(250));
+        assert_eq!(
+            instruments(notes),
+            [Instrument::HiHat, Instrument::BassDrum]
+        );
+    }
+
+    #[test]
+    fn every_note_plays_once_per_loop() {
+        let mut score = Score::standard();
+        let start = Instant::now();
+        score.update(bpm(120), start);
+
+        // Two 8 beat loops and a bit, in uneven updates.
+        let mut notes = Vec::new();
+        let mut ms = 0;
+        for step in [7, 130, 1, 999, 250, 3, 1610, 500, 2000, 2600] {
+            ms += step;
+            notes.extend(score.update(bpm(120), start + Duration::from_millis(ms)));
+        }
+        assert_eq!(ms, 8100);
+
+        // Both loops, then the first step of the third.
+        assert_eq!(count(&notes, Instrument::HiHat), 4 * 2 + 1);
+        assert_eq!(count(&notes, Instrument::Snare), 2);
+        assert_eq!(count(&notes, Instrument::BassDrum), 2 + 1);
+    }
+
+    #[test]
+    fn notes_across_the_loop_boundary_are_in_time_order() {
+        let mut score = bar(vec![
+            Track::new(Instrument::Snare, vec![0]),
+            Track::new(Instrument::HiHat, vec![3]),
+        ]);
+        score.set_beat(2.5);
+        let start = Instant::now();
+        score.update(bpm(60), start);
+
+        let notes = score.update(bpm(60), start + Duration::from_secs(2));
+        assert_eq!(instruments(notes), [Instrument::HiHat, Instrument::Snare]);
+        assert_eq!(score.get_beat(), 4.5);
+    }
+
+    #[test]
+    fn tempo_changes_take_effect_from_the_next_update() {
+        let mut score = bar(vec![Track::new(Instrument::HiHat, vec![0, 1, 2, 3])]);
+        let start = Instant::now();
+        score.update(bpm(60), start);
+
+        let slow = score.update(bpm(60), start + Duration::from_secs(1));
+        assert_eq!(slow.len(), 1);
+        let fast = score.update(bpm(120), start + Duration::from_secs(2));
+        assert_eq!(fast.len(), 2);
+        assert_eq!(score.get_beat(), 3.0);
+    }
+
+    #[test]
+    fn pitched_tracks_keep_their_pitch() {
+        let mut score = bar(vec![Track::pitched(Instrument::BassDrum, -500, vec![0])]);
+        let notes = score.advance(1.0);
+        assert_eq!(
+            notes,
+            [NoteEvent::new(Instrument::BassDrum).with_pitch(-500)]
+        );
+    }
 }
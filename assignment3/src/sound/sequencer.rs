@@ -0,0 +1,318 @@
+/**
+ * Plays scores, switching between them only on bar lines.
+ */
+use std::time::Instant;
+
+use crate::{
+    sound::{
+        Beat, NoteEvent,
+        score::{Score, ScoreType},
+        song::{End, Song},
+    },
+    units::Bpm,
+};
+
+/// Where the sequencer is within a song.
+struct SongCursor {
+    song: Song,
+    section: usize,
+    in_fill: bool,
+    bars_left: u32,
+}
+
+pub struct Sequencer {
+    score: Score,
+    queued: Option<Score>,
+    song: Option<SongCursor>,
+
+    prev: Option<Instant>,
+    playing: bool,
+    /// Total beats played since the last start from the top.
+    beats: Beat,
+    /// Length of the bar starting at each bar line crossed, since they were last taken.
+    bar_lines: Vec<Beat>,
+    /// Beats to wait before moving on, after being shifted back.
+    hold: Beat,
+}
+
+impl Sequencer {
+    pub fn new(score: Score) -> Self {
+        Self {
+            score,
+            queued: None,
+            song: None,
+            prev: None,
+            playing: true,
+            beats: 0.0,
+            bar_lines: Vec::new(),
+            hold: 0.0,
+        }
+    }
+
+    /// The score that is currently playing.
+    pub fn score(&self) -> &Score {
+        &self.score
+    }
+
+    /// The score that plays on from the next bar line: the queued one if there is one, else the one playing.
+    pub fn next_score(&self) -> &Score {
+        self.queued.as_ref().unwrap_or(&self.score)
+    }
+
+    /// The score that plays on from the next bar line, for live editing. Edits made while a score is queued
+    /// go to that score, rather than to the one it is about to replace.
+    pub fn next_score_mut(&mut self) -> &mut Score {
+        self.queued.as_mut().unwrap_or(&mut self.score)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Total beats played since the last start from the top.
+    pub fn beats(&self) -> Beat {
+        self.beats
+    }
+
+    /// The bar lines crossed since the last call, as the length in beats of the bar that starts at each.
+    /// A score or song can change the time signature on any of them.
+    pub fn take_bar_lines(&mut self) -> Vec<Beat> {
+        std::mem::take(&mut self.bar_lines)
+    }
+
+    /// Beat of the playhead in the score, less any wait still to come after being shifted back.
+    pub fn playhead(&self) -> Beat {
+        self.score.get_beat() - self.hold
+    }
+
+    /// Play from the top of the score, or the start of the song.
+    pub fn start(&mut self) {
+        if let Some(score) = self.queued.take() {
+            self.score = score;
+        }
+        if let Some(cursor) = &mut self.song {
+            let first = cursor.song.sections[0];
+            cursor.section = 0;
+            cursor.in_fill = false;
+            self.score = first.pattern.apply();
+            cursor.bars_left = self.score.bars() * first.repeats;
+        }
+        self.score.set_beat(0.0);
+        self.beats = 0.0;
+        self.hold = 0.0;
+        self.playing = true;
+    }
+
+    /// Pause, keeping the position.
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    /// Play again from where it was stopped.
+    pub fn resume(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn song(&self) -> Option<&Song> {
+        self.song.as_ref().map(|cursor| &cursor.song)
+    }
+
+    /// Switch to a new score on the next bar line. Leaves song mode.
+    pub fn queue(&mut self, score: ScoreType) {
+        self.queue_pattern(score.apply());
+    }
+
+    /// Switch to an edited or saved score on the next bar line. Leaves song mode.
+    pub fn queue_pattern(&mut self, score: Score) {
+        self.song = None;
+        self.queue_score(score);
+    }
+
+    /// Start playing a song from its first section, on the next bar line.
+    pub fn play_song(&mut self, song: Song) {
+        let first = song.sections[0].pattern.apply();
+        self.song = Some(SongCursor {
+            bars_left: first.bars() * song.sections[0].repeats,
+            song,
+            section: 0,
+            in_fill: false,
+        });
+        self.queue_score(first);
+    }
+
+    pub fn update(&mut self, bpm: Bpm, now: Instant) -> Vec<NoteEvent> {
+        let Some(prev) = self.prev.replace(now) else {
+            return Vec::new();
+        };
+
+        if !self.playing {
+            return Vec::new();
+        }
+
+        let elapsed: Beat = (now - prev).as_secs_f64() * (f64::from(bpm) / 60.0);
+        self.play(elapsed, true)
+    }
+
+    /// Move the playhead by `beats`, without playing the notes in between.
+    ///
+    /// Moving forward crosses bar lines like playing does, so queued scores and songs move on.
+    /// Moving back waits that long before playing on instead, so no bar line or note comes around twice.
+    pub fn shift(&mut self, beats: Beat) {
+        if beats < 0.0 {
+            self.hold -= beats;
+        } else {
+            self.play(beats, false);
+        }
+    }
+
+    /// Move the playhead forward, getting the notes passed if `sound` is set.
+    fn play(&mut self, beats: Beat, sound: bool) -> Vec<NoteEvent> {
+        let held = beats.min(self.hold);
+        self.hold -= held;
+        let mut remaining = beats - held;
+        let mut events = Vec::new();
+        self.beats += remaining;
+
+        // Play up to each bar line, and give the song a chance to change the score there.
+        while self.playing {
+            let to_bar = self.score.beats_to_bar();
+            let step = remaining.min(to_bar);
+            let notes = self.score.advance(step);
+            if sound {
+                events.extend(notes);
+            }
+            if remaining < to_bar {
+                break;
+            }
+
+            remaining -= to_bar;
+            self.next_bar();
+            self.bar_lines.push(self.score.bar_length());
+        }
+
+        events
+    }
+
+    fn queue_score(&mut self, score: Score) {
+        if self.playing {
+            self.queued = Some(score);
+        } else {
+            self.score = score;
+            self.playing = true;
+        }
+    }
+
+    /// Called on every bar line.
+    fn next_bar(&mut self) {
+        if let Some(score) = self.queued.take() {
+            self.score = score;
+            return;
+        }
+
+        let Some(cursor) = &mut self.song else {
+            return;
+        };
+
+        cursor.bars_left = cursor.bars_left.saturating_sub(1);
+        if cursor.bars_left > 0 {
+            return;
+        }
+
+        let section = cursor.song.sections[cursor.section];
+        let (pattern, repeats) = match section.fill {
+            Some(fill) if !cursor.in_fill => {
+                cursor.in_fill = true;
+                (fill, 1)
+            }
+            _ => {
+                cursor.in_fill = false;
+                if cursor.section + 1 < cursor.song.sections.len() {
+                    cursor.section += 1;
+                } else {
+                    match cursor.song.end {
+                        End::Loop => cursor.section = 0,
+                        End::Hold => {}
+                        End::Stop => {
+                            self.song = None;
+                            self.playing = false;
+                            return;
+                        }
+                    }
+                }
+                let section = cursor.song.sections[cursor.section];
+                (section.pattern, section.repeats)
+            }
+        };
+
+        self.score = pattern.apply();
+        cursor.bars_left = self.score.bars() * repeats;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::sound::Instrument;
+
+    fn bpm(value: u32) -> Bpm {
+        Bpm::try_from(value).unwrap()
+    }
+
+    #[test]
+    fn shifting_forward_crosses_bar_lines() {
+        let mut sequencer = Sequencer::new(ScoreType::Standard.apply());
+        sequencer.queue(ScoreType::Funky);
+
+        sequencer.shift(5.0);
+        assert_eq!(sequencer.take_bar_lines(), [4.0]);
+        // The queued score took over on the bar line, so it is a beat in.
+        assert_eq!(sequencer.score().t, ScoreType::Funky);
+        assert_eq!(sequencer.playhead(), 1.0);
+    }
+
+    #[test]
+    fn edits_go_to_the_queued_score() {
+        let mut sequencer = Sequencer::new(ScoreType::Standard.apply());
+        sequencer.queue(ScoreType::Funky);
+        let grid = sequencer.next_score().grid(Instrument::Snare);
+        sequencer.next_score_mut().toggle(Instrument::Snare, 1);
+        let edited = sequencer.next_score().grid(Instrument::Snare);
+        assert_ne!(edited, grid);
+
+        sequencer.shift(4.0);
+        assert_eq!(sequencer.score().t, ScoreType::Funky);
+        assert_eq!(sequencer.score().grid(Instrument::Snare), edited);
+    }
+
+    #[test]
+    fn shifting_back_waits_without_playing() {
+        let mut sequencer = Sequencer::new(ScoreType::Standard.apply());
+        let start = Instant::now();
+        sequencer.update(bpm(60), start);
+        sequencer.update(bpm(60), start + Duration::from_secs(3));
+        sequencer.shift(-2.0);
+        assert_eq!(sequencer.playhead(), 1.0);
+
+        // The 2 beats are waited out, and only then does the playhead move on.
+        let notes = sequencer.update(bpm(60), start + Duration::from_secs(5));
+        assert!(notes.is_empty());
+        assert_eq!(sequencer.score().get_beat(), 3.0);
+        sequencer.update(bpm(60), start + Duration::from_secs(6));
+        assert_eq!(sequencer.playhead(), 4.0);
+        assert_eq!(sequencer.take_bar_lines(), [4.0]);
+    }
+
+    #[test]
+    fn bar_lines_keep_the_length_of_each_bar() {
+        let mut sequencer = Sequencer::new(ScoreType::Standard.apply());
+        sequencer.queue(ScoreType::Five);
+        sequencer.shift(4.0);
+        sequencer.queue(ScoreType::Standard);
+        sequencer.shift(5.0);
+
+        assert_eq!(sequencer.take_bar_lines(), [5.0, 4.0]);
+        assert!(sequencer.take_bar_lines().is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+/**
+ * Song arrangements: a list of patterns with repeat counts and fills.
+ */
+use std::{fmt::Display, str::FromStr};
+
+use crate::sound::score::ScoreType;
+
+/// One part of a song. The pattern loops `repeats` times, then the fill (if any) plays once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Section {
+    pub pattern: ScoreType,
+    pub repeats: u32,
+    pub fill: Option<ScoreType>,
+}
+
+/// What happens after the last section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum End {
+    /// Start again from the first section.
+    Loop,
+    /// Keep looping the last pattern.
+    Hold,
+    /// Stop the sequencer.
+    #[default]
+    Stop,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Song {
+    pub sections: Vec<Section>,
+    pub end: End,
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.pattern.to_index(), self.repeats)?;
+        if let Some(fill) = self.fill {
+            write!(f, "+{}", fill.to_index())?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for End {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                End::Loop => "loop",
+                End::Hold => "hold",
+                End::Stop => "stop",
+            }
+        )
+    }
+}
+
+impl Display for Song {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for section in &self.sections {
+            write!(f, "{} ", section)?;
+        }
+        write!(f, "{}", self.end)
+    }
+}
+
+impl FromStr for Section {
+    type Err = ();
+
+    /// Parse "<mode>x<repeats>" with an optional "+<fill mode>", e.g. "1x4+2".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (s, fill) = match s.split_once('+') {
+            Some((s, fill)) => (s, Some(fill.parse().map_err(|_| ())?)),
+            None => (s, None),
+        };
+        let (pattern, repeats) = match s.split_once('x') {
+            Some((pattern, repeats)) => (pattern, repeats.parse().map_err(|_| ())?),
+            None => (s, 1),
+        };
+        let pattern = pattern.parse().map_err(|_| ())?;
+
+        if repeats == 0 {
+            return Err(());
+        }
+
+        Ok(Section {
+            pattern: ScoreType::from_index(pattern),
+            repeats,
+            fill: fill.map(ScoreType::from_index),
+        })
+    }
+}
+
+impl FromStr for Song {
+    type Err = ();
+
+    /// Parse a whitespace separated list of sections, optionally ending with "loop", "hold" or "stop".
+    ///
+    /// Example: "1x4+2 2x4 1x2 stop"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut sections = Vec::new();
+        let mut end = End::default();
+
+        let mut parts = s.split_whitespace().peekable();
+        while let Some(part) = parts.next() {
+            let marker = match part.to_lowercase().as_str() {
+                "loop" => Some(End::Loop),
+                "hold" => Some(End::Hold),
+                "stop" => Some(End::Stop),
+                _ => None,
+            };
+
+            match marker {
+                // The end marker must be last.
+                Some(marker) if parts.peek().is_none() => end = marker,
+                Some(_) => return Err(()),
+                None => sections.push(part.parse()?),
+            }
+        }
+
+        if sections.is_empty() {
+            return Err(());
+        }
+
+        Ok(Song { sections, end })
+    }
+}
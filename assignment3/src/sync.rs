@@ -0,0 +1,297 @@
+/**
+ * Keeps several beat_box units in time over UDP.
+ *
+ * The leader broadcasts its tempo, score and beat, stamped with its own clock:
+ *   "sync <leader_us> <bpm> <mode> <beat>"
+ * Followers estimate the leader's clock with ping/pong round trips, like NTP:
+ *   "ping <follower_us>"  ->  "pong <follower_us> <leader_us>"
+ * so each sync message can be projected forward by the network delay.
+ */
+use std::{
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    sound::{Beat, score::ScoreType, sequencer::Sequencer},
+    udp::UdpConn,
+    units::Bpm,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Leader,
+    Follower,
+}
+
+/// The shared musical state, at one moment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncState {
+    pub bpm: Bpm,
+    pub score: ScoreType,
+    pub beat: Beat,
+}
+
+/// One round trip measurement of the leader's clock.
+#[derive(Debug, Clone, Copy)]
+struct ClockSample {
+    round_trip: i64,
+    offset: i64,
+}
+
+pub struct NetSync {
+    conn: UdpConn,
+    role: Role,
+    /// Local clock zero. Timestamps on the wire are microseconds since each unit's own epoch.
+    epoch: Instant,
+
+    /// Leader: where sync messages go. Follower: the leader, once heard from.
+    peer: Option<SocketAddr>,
+    period: Duration,
+    last_send: Option<Instant>,
+
+    samples: VecDeque<ClockSample>,
+    latest: Option<(i64, SyncState)>,
+}
+
+impl NetSync {
+    /// Round trips kept for the clock offset estimate.
+    const SAMPLE_COUNT: usize = 16;
+
+    /// A leader sends sync messages to `addr`, which may be a broadcast address. A follower listens on its port.
+    pub fn open(role: Role, addr: SocketAddr) -> io::Result<Self> {
+        match role {
+            Role::Leader => {
+                let conn = UdpConn::bind("0.0.0.0:0")?;
+                conn.set_broadcast(true)?;
+                Ok(Self::new(conn, role, Some(addr)))
+            }
+            Role::Follower => {
+                let conn = UdpConn::bind(&format!("0.0.0.0:{}", addr.port()))?;
+                Ok(Self::new(conn, role, None))
+            }
+        }
+    }
+
+    fn new(conn: UdpConn, role: Role, peer: Option<SocketAddr>) -> Self {
+        Self {
+            conn,
+            role,
+            epoch: Instant::now(),
+            peer,
+            period: Duration::from_millis(100),
+            last_send: None,
+            samples: VecDeque::new(),
+            latest: None,
+        }
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    /// Share the sequencer's playhead as leader, or pull it towards the leader's as follower.
+    /// Returns the tempo to play at.
+    pub fn update(&mut self, sequencer: &mut Sequencer, bpm: Bpm, now: Instant) -> io::Result<Bpm> {
+        match self.role {
+            Role::Leader => {
+                let state = SyncState {
+                    bpm,
+                    score: sequencer.score().t,
+                    beat: sequencer.playhead(),
+                };
+                self.lead(state, now)?;
+                Ok(bpm)
+            }
+            Role::Follower => Ok(match self.follow(now)? {
+                Some(state) => apply(state, sequencer),
+                None => bpm,
+            }),
+        }
+    }
+
+    /// Leader: answer pings, and send the current state every period.
+    pub fn lead(&mut self, state: SyncState, now: Instant) -> io::Result<()> {
+        let local = self.micros(now);
+
+        while let Some((text, addr)) = self.conn.try_recv()? {
+            let mut parts = text.split_whitespace();
+            if parts.next() == Some("ping")
+                && let Some(sent) = parts.next()
+            {
+                self.conn
+                    .send_to(&format!("pong {} {}", sent, local), addr)?;
+            }
+        }
+
+        if self.is_due(now)
+            && let Some(peer) = self.peer
+        {
+            self.conn.send_to(
+                &format!(
+                    "sync {} {} {} {}",
+                    local,
+                    state.bpm.as_f64(),
+                    state.score.to_index(),
+                    state.beat
+                ),
+                peer,
+            )?;
+            self.last_send = Some(now);
+        }
+
+        Ok(())
+    }
+
+    /// Follower: ping the leader every period, and get the leader's state projected to `now`.
+    pub fn follow(&mut self, now: Instant) -> io::Result<Option<SyncState>> {
+        let local = self.micros(now);
+
+        while let Some((text, addr)) = self.conn.try_recv()? {
+            let parts: Vec<&str> = text.split_whitespace().collect();
+            match parts.as_slice() {
+                ["sync", time, bpm, mode, beat] => {
+                    let (Ok(time), Ok(bpm), Ok(mode), Ok(beat)) =
+                        (time.parse(), bpm.parse(), mode.parse(), beat.parse())
+                    else {
+                        continue;
+                    };
+                    self.peer = Some(addr);
+                    let state = SyncState {
+                        bpm: Bpm::saturating_from(bpm),
+                        score: ScoreType::from_index(mode),
+                        beat,
+                    };
+                    self.latest = Some((time, state));
+                }
+                ["pong", sent, leader] => {
+                    let (Ok(sent), Ok(leader)) = (sent.parse::<i64>(), leader.parse::<i64>())
+                    else {
+                        continue;
+                    };
+                    // Assume the leader stamped the reply half way through the round trip.
+                    self.samples.push_back(ClockSample {
+                        round_trip: local - sent,
+                        offset: leader - (sent + local) / 2,
+                    });
+                    if self.samples.len() > Self::SAMPLE_COUNT {
+                        self.samples.pop_front();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if self.is_due(now)
+            && let Some(peer) = self.peer
+        {
+            self.conn.send_to(&format!("ping {}", local), peer)?;
+            self.last_send = Some(now);
+        }
+
+        let (Some(offset), Some((time, state))) = (self.offset(), self.latest) else {
+            return Ok(None);
+        };
+
+        let elapsed = (local + offset - time) as f64 / 1_000_000.0;
+        Ok(Some(SyncState {
+            beat: state.beat + elapsed * state.bpm.as_f64() / 60.0,
+            ..state
+        }))
+    }
+
+    /// Leader clock minus local clock, in microseconds. Taken from the fastest recent round trip, which had the least queueing delay.
+    fn offset(&self) -> Option<i64> {
+        self.samples
+            .iter()
+            .min_by_key(|s| s.round_trip)
+            .map(|s| s.offset)
+    }
+
+    fn is_due(&self, now: Instant) -> bool {
+        self.last_send.is_none_or(|last| now - last >= self.period)
+    }
+
+    fn micros(&self, now: Instant) -> i64 {
+        (now - self.epoch).as_micros() as i64
+    }
+}
+
+/// How far to move a follower's beat, given how far it is behind the leader.
+///
+/// Small errors are slewed out over several updates so notes aren't skipped or doubled; large ones jump straight there.
+/// The error is wrapped to the nearest loop of the score, since only the phase within the loop matters.
+pub fn phase_correction(leader: Beat, follower: Beat, length: Beat) -> Beat {
+    const JUMP: Beat = 0.5;
+    const SLEW: Beat = 0.2;
+
+    let error = (leader - follower + length / 2.0).rem_euclid(length) - length / 2.0;
+    if error.abs() > JUMP {
+        error
+    } else {
+        error * SLEW
+    }
+}
+
+/// Bring a follower's sequencer in line with the leader, and get the tempo to play at.
+///
+/// A different score is queued, so it changes on the next bar line like any other score change.
+/// The playhead is moved through the sequencer, so bar lines it jumps over still count.
+fn apply(state: SyncState, sequencer: &mut Sequencer) -> Bpm {
+    if sequencer.score().t != state.score {
+        sequencer.queue(state.score);
+    } else {
+        let length = sequencer.score().length();
+        sequencer.shift(phase_correction(state.beat, sequencer.playhead(), length));
+    }
+    state.bpm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open(role: Role) -> NetSync {
+        NetSync::new(UdpConn::bind("127.0.0.1:0").unwrap(), role, None)
+    }
+
+    #[test]
+    fn follower_locks_to_leader() {
+        let mut leader = open(Role::Leader);
+        let mut follower = open(Role::Follower);
+        leader.peer = Some(follower.conn.local_addr().unwrap());
+
+        // Start out of phase, and at a different tempo.
+        let leader_bpm = Bpm::try_from(100).unwrap();
+        let mut follower_bpm = Bpm::try_from(120).unwrap();
+        let mut leading = Sequencer::new(ScoreType::Standard.apply());
+        let mut following = Sequencer::new(ScoreType::Standard.apply());
+        following.shift(3.0);
+
+        let start = Instant::now();
+        let mut checked = 0;
+        for ms in (0..4000).step_by(5) {
+            let now = start + Duration::from_millis(ms);
+            leading.update(leader_bpm, now);
+            following.update(follower_bpm, now);
+            leader.update(&mut leading, leader_bpm, now).unwrap();
+            follower_bpm = follower.update(&mut following, follower_bpm, now).unwrap();
+
+            // Give the follower time to settle.
+            if ms >= 1500 {
+                let error = phase_correction(leading.playhead(), following.playhead(), 8.0);
+                assert_eq!(follower_bpm, leader_bpm);
+                assert!(
+                    error.abs() < 0.05,
+                    "follower is {} beats behind at {} ms",
+                    error,
+                    ms
+                );
+                checked += 1;
+            }
+        }
+        assert!(checked > 100);
+    }
+}
@@ -17,32 +17,45 @@ impl UdpConn {
         Ok(UdpConn { socket })
     }
 
-    /// Try to receive a command. Returns Ok(None) if no data currently.
-    pub fn try_recv_command(
-        &self,
-    ) -> std::io::Result<Option<(Option<command::Command>, SocketAddr)>> {
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Allow sending to broadcast addresses.
+    pub fn set_broadcast(&self, broadcast: bool) -> std::io::Result<()> {
+        self.socket.set_broadcast(broadcast)
+    }
+
+    /// Try to receive a UTF-8 message. Returns Ok(None) if no data currently, and an empty string for invalid UTF-8.
+    pub fn try_recv(&self) -> std::io::Result<Option<(String, SocketAddr)>> {
         let mut buf = [0u8; 1024];
         match self.socket.recv_from(&mut buf) {
-            Ok((n, addr)) => {
-                if n == 0 {
-                    return Ok(Some((None, addr)));
-                }
-                if let Ok(s) = std::str::from_utf8(&buf[..n]) {
-                    match s.trim().parse::<command::Command>() {
-                        Ok(cmd) => Ok(Some((Some(cmd), addr))),
-                        Err(_) => Ok(Some((None, addr))),
-                    }
-                } else {
-                    Ok(Some((None, addr)))
-                }
-            }
+            Ok((n, addr)) => Ok(Some((
+                std::str::from_utf8(&buf[..n])
+                    .unwrap_or_default()
+                    .to_owned(),
+                addr,
+            ))),
             Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(None),
             Err(e) => Err(e),
         }
     }
 
+    /// Try to receive a command. Returns Ok(None) if no data currently.
+    pub fn try_recv_command(
+        &self,
+    ) -> std::io::Result<Option<(Option<command::Command>, SocketAddr)>> {
+        Ok(self
+            .try_recv()?
+            .map(|(s, addr)| (s.trim().parse::<command::Command>().ok(), addr)))
+    }
+
     /// Send a raw UTF-8 reply to the given address
     pub fn send_reply(&self, reply: Arc<str>, dest: SocketAddr) -> std::io::Result<usize> {
-        self.socket.send_to(reply.as_bytes(), dest)
+        self.send_to(&reply, dest)
+    }
+
+    pub fn send_to(&self, text: &str, dest: SocketAddr) -> std::io::Result<usize> {
+        self.socket.send_to(text.as_bytes(), dest)
     }
 }
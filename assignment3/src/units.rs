@@ -48,7 +48,7 @@ impl Display for Volume {
 pub struct Bpm(f64);
 
 impl Bpm {
-    const MIN: f64 = 40.0;
+    pub const MIN: f64 = 40.0;
     const MAX: f64 = 300.0;
 
     /// Allow accumulating the encoder values.
@@ -56,6 +56,11 @@ impl Bpm {
         Self((self.0 + value).clamp(Self::MIN, Self::MAX))
     }
 
+    /// Create from a measured tempo, clamped to the allowed range.
+    pub fn saturating_from(value: f64) -> Self {
+        Self(value.clamp(Self::MIN, Self::MAX))
+    }
+
     pub fn as_f64(self) -> f64 {
         self.0
     }
@@ -0,0 +1,84 @@
+/**
+ * Runs a leader and a follower as separate processes on loopback, and checks the follower's playhead
+ * stays on the leader's for several bars.
+ */
+use std::{
+    net::UdpSocket,
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+/// One line of `--sync-probe` output.
+struct Sample {
+    micros: u128,
+    beat: f64,
+    length: f64,
+    bpm: f64,
+}
+
+fn spawn(role: &str, addr: &str, seconds: f64, tempo: u32) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_beat_box"))
+        .args(["--sync", role, "--sync-addr", addr])
+        .args(["--sync-probe", &seconds.to_string()])
+        .args(["--tempo", &tempo.to_string()])
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("beat_box must start.")
+}
+
+fn samples(child: Child) -> Vec<Sample> {
+    let output = child.wait_with_output().expect("beat_box must finish.");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            Sample {
+                micros: parts[0].parse().unwrap(),
+                beat: parts[1].parse().unwrap(),
+                length: parts[2].parse().unwrap(),
+                bpm: parts[3].parse().unwrap(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn follower_locks_to_leader() {
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("127.0.0.1:{}", port);
+
+    // Start out of phase, and at a different tempo. Four bars of 4/4 at 120 BPM are checked after settling.
+    let follower = spawn("follower", &addr, 11.5, 100);
+    thread::sleep(Duration::from_millis(300));
+    let leader = spawn("leader", &addr, 11.0, 120);
+
+    let leader = samples(leader);
+    let follower = samples(follower);
+
+    // Give the follower time to settle, however slowly the two processes start.
+    let from = leader[0].micros.max(follower[0].micros) + 2_500_000;
+    let to = leader.last().unwrap().micros;
+    assert!(to - from >= 8_000_000, "the leader stopped early");
+
+    let mut checked = 0;
+    let mut worst: f64 = 0.0;
+    for f in follower.iter().filter(|f| (from..to).contains(&f.micros)) {
+        let l = leader.iter().rev().find(|l| l.micros <= f.micros).unwrap();
+        let expected = l.beat + (f.micros - l.micros) as f64 / 1_000_000.0 * l.bpm / 60.0;
+        let error = (expected - f.beat + f.length / 2.0).rem_euclid(f.length) - f.length / 2.0;
+
+        assert_eq!(f.bpm, l.bpm);
+        worst = worst.max(error.abs());
+        checked += 1;
+    }
+    // A tenth of a beat is 50 ms at 120 BPM, far more than loopback delay or a few late polls.
+    assert!(worst < 0.1, "follower was up to {} beats off", worst);
+    assert!(checked > 1000);
+}
@@ -0,0 +1,231 @@
+/**
+ * What every ADC driver provides, and the scan thread that works with any of them.
+ */
+use std::{
+    fmt::Display,
+    io,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The channels that can be polled. Not every chip has all of them.
+#[derive(Debug, Clone, Copy)]
+pub enum Channel {
+    CH0 = 0,
+    CH1 = 1,
+    CH2 = 2,
+    CH3 = 3,
+    CH4 = 4,
+    CH5 = 5,
+    CH6 = 6,
+    CH7 = 7,
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            Self::CH0 => "CH0",
+            Self::CH1 => "CH1",
+            Self::CH2 => "CH2",
+            Self::CH3 => "CH3",
+            Self::CH4 => "CH4",
+            Self::CH5 => "CH5",
+            Self::CH6 => "CH6",
+            Self::CH7 => "CH7",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// A pair of channels measured against each other, named positive then negative.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy)]
+pub enum Pair {
+    CH0_CH1 = 0,
+    CH1_CH0 = 1,
+    CH2_CH3 = 2,
+    CH3_CH2 = 3,
+    CH4_CH5 = 4,
+    CH5_CH4 = 5,
+    CH6_CH7 = 6,
+    CH7_CH6 = 7,
+}
+
+impl Pair {
+    /// The same pair, with positive and negative swapped.
+    pub fn reversed(self) -> Self {
+        match self {
+            Self::CH0_CH1 => Self::CH1_CH0,
+            Self::CH1_CH0 => Self::CH0_CH1,
+            Self::CH2_CH3 => Self::CH3_CH2,
+            Self::CH3_CH2 => Self::CH2_CH3,
+            Self::CH4_CH5 => Self::CH5_CH4,
+            Self::CH5_CH4 => Self::CH4_CH5,
+            Self::CH6_CH7 => Self::CH7_CH6,
+            Self::CH7_CH6 => Self::CH6_CH7,
+        }
+    }
+}
+
+/// What to measure: one channel against ground, or the signed difference across a pair.
+#[derive(Debug, Clone, Copy)]
+pub enum Input {
+    Single(Channel),
+    Differential(Pair),
+}
+
+impl From<Channel> for Input {
+    fn from(channel: Channel) -> Self {
+        Self::Single(channel)
+    }
+}
+
+impl From<Pair> for Input {
+    fn from(pair: Pair) -> Self {
+        Self::Differential(pair)
+    }
+}
+
+/// An analog to digital converter. Readings are scaled to the chip's full scale,
+/// so [0.0, 1.0) single-ended and (-1.0, 1.0) differential, whatever its resolution.
+pub trait Adc {
+    /// Voltage at full scale, a reading of 1.0.
+    fn vref(&self) -> f64;
+
+    /// Get a single measurement from the ADC.
+    fn get_single(&mut self, input: Input) -> io::Result<f64>;
+
+    /// Get the median of multiple measurements from the ADC.
+    fn get_median(&mut self, input: Input, sample_count: usize) -> io::Result<f64> {
+        let mut samples: Vec<_> = (0..sample_count)
+            .map(|_| self.get_single(input))
+            .collect::<Result<_, _>>()?;
+
+        median(&mut samples)
+    }
+
+    /// Get a single voltage measurement from the ADC. Based on the assumed vref.
+    fn get_voltage_single(&mut self, input: Input) -> io::Result<f64> {
+        let vref = self.vref();
+        self.get_single(input).map(|sample| sample * vref)
+    }
+
+    /// Collect many voltage samples, and calculate the median. Used to counteract noise and bad readings.
+    fn get_voltage_median(&mut self, input: Input, sample_count: usize) -> io::Result<f64> {
+        let vref = self.vref();
+        self.get_median(input, sample_count)
+            .map(|sample| sample * vref)
+    }
+
+    /// Read every input `sample_count` times, and take the median of each.
+    /// Drivers that can batch conversions should override this.
+    fn read_many(&mut self, inputs: &[Input], sample_count: usize) -> io::Result<Vec<f64>> {
+        inputs
+            .iter()
+            .map(|&input| self.get_median(input, sample_count))
+            .collect()
+    }
+}
+
+/// Inputs that are read together, at a fixed rate.
+#[derive(Debug, Clone)]
+pub struct ScanGroup {
+    pub inputs: Vec<Input>,
+    pub period: Duration,
+    /// Measurements per input, of which the median is taken.
+    pub sample_count: usize,
+}
+
+/// One scan of a group, in the same order as its inputs.
+#[derive(Debug, Clone)]
+pub struct Reading {
+    /// Index of the group in the list given to the scan thread.
+    pub group: usize,
+    /// Scaled to [0.0, 1.0), or (-1.0, 1.0) for differential inputs.
+    pub values: Vec<f64>,
+    pub vref: f64,
+    pub time: Instant,
+}
+
+impl Reading {
+    pub fn voltages(&self) -> impl Iterator<Item = f64> {
+        self.values.iter().map(|value| value * self.vref)
+    }
+}
+
+/// Create the thread that scans each group at its own rate, and sends timestamped readings.
+pub fn make_scan_thread<A: Adc + Send + 'static>(
+    mut adc: A,
+    groups: Vec<ScanGroup>,
+) -> (
+    thread::JoinHandle<io::Result<()>>,
+    mpsc::Receiver<Reading>,
+    mpsc::Sender<()>,
+) {
+    let (data, reading_rx) = mpsc::channel();
+    let (scan_kill_tx, kill) = mpsc::channel::<()>();
+
+    let handle = thread::spawn::<_, io::Result<()>>(move || {
+        let start = Instant::now();
+        let mut due = vec![start; groups.len()];
+
+        loop {
+            match kill.try_recv() {
+                Err(mpsc::TryRecvError::Empty) => {}
+                Ok(_) | Err(mpsc::TryRecvError::Disconnected) => break Ok(()),
+            };
+
+            let Some((group, &next)) = due.iter().enumerate().min_by_key(|(_, time)| **time) else {
+                break Ok(());
+            };
+            if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+
+            let ScanGroup {
+                inputs,
+                period,
+                sample_count,
+            } = &groups[group];
+            let values = adc.read_many(inputs, *sample_count)?;
+            let now = Instant::now();
+
+            // Skip missed scans, rather than bursting to catch up.
+            due[group] = (next + *period).max(now);
+
+            let reading = Reading {
+                group,
+                values,
+                vref: adc.vref(),
+                time: now,
+            };
+            if data.send(reading).is_err() {
+                break Ok(());
+            }
+        }
+    });
+
+    (handle, reading_rx, scan_kill_tx)
+}
+
+/// The median of the samples. Fails if there are none, as when asked for a sample count of 0.
+pub fn median(samples: &mut [f64]) -> io::Result<f64> {
+    if samples.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the median needs at least one sample",
+        ));
+    }
+    let mid = samples.len() / 2;
+    let (_, median, _) = samples.select_nth_unstable_by(mid, f64::total_cmp);
+    Ok(*median)
+}
+
+/// The error for an input the chip can't measure.
+pub fn unsupported(input: Input) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{:?} is not supported by this ADC", input),
+    )
+}
@@ -0,0 +1,189 @@
+/**
+ * Hardware interface for the ADS1015 and ADS1115 I2C ADCs.
+ *
+ * Each read starts a single-shot conversion, and waits for it to finish.
+ * Works with any embedded-hal `I2c`. On Linux, `ADS1x15::new` opens an i2c-dev device.
+ */
+use std::{
+    io, thread,
+    time::{Duration, Instant},
+};
+
+use embedded_hal::i2c::I2c;
+use linux_embedded_hal::I2cdev;
+
+use crate::adc::{Adc, Channel, Input, Pair, unsupported};
+
+/// Which chip is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    /// 12-bit, up to 3300 samples per second.
+    ADS1015,
+    /// 16-bit, up to 860 samples per second.
+    #[default]
+    ADS1115,
+}
+
+impl Variant {
+    /// Readings per full scale, in each direction.
+    fn max_raw(self) -> f64 {
+        match self {
+            Variant::ADS1015 => 2048.0,
+            Variant::ADS1115 => 32768.0,
+        }
+    }
+
+    /// Right align the conversion register.
+    fn parse(self, register: [u8; 2]) -> i16 {
+        let value = i16::from_be_bytes(register);
+        match self {
+            Variant::ADS1015 => value >> 4,
+            Variant::ADS1115 => value,
+        }
+    }
+}
+
+/// Programmable gain, named by the voltage at full scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Gain {
+    V6_144 = 0,
+    #[default]
+    V4_096 = 1,
+    V2_048 = 2,
+    V1_024 = 3,
+    V0_512 = 4,
+    V0_256 = 5,
+}
+
+impl Gain {
+    pub fn full_scale(self) -> f64 {
+        match self {
+            Gain::V6_144 => 6.144,
+            Gain::V4_096 => 4.096,
+            Gain::V2_048 => 2.048,
+            Gain::V1_024 => 1.024,
+            Gain::V0_512 => 0.512,
+            Gain::V0_256 => 0.256,
+        }
+    }
+}
+
+/// Settings for the ADC.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// 0x48 to 0x4B, set by the ADDR pin.
+    pub address: u8,
+    pub variant: Variant,
+    pub gain: Gain,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            address: 0x48,
+            variant: Variant::default(),
+            gain: Gain::default(),
+        }
+    }
+}
+
+/// ADS1015 or ADS1115 connected over I2C.
+pub struct ADS1x15<I2C = I2cdev> {
+    i2c: I2C,
+    address: u8,
+    variant: Variant,
+    gain: Gain,
+}
+
+impl ADS1x15 {
+    /// Open a connection with an ADS1x15 over i2c-dev, like "/dev/i2c-1".
+    pub fn new<P: AsRef<std::path::Path>>(path: P, config: Config) -> io::Result<Self> {
+        let i2c = I2cdev::new(path).map_err(io::Error::other)?;
+        Ok(Self::from_device(i2c, config))
+    }
+}
+
+impl<I2C: I2c> ADS1x15<I2C> {
+    const CONVERSION: u8 = 0x00;
+    const CONFIG: u8 = 0x01;
+
+    /// Give up on a conversion that hasn't finished in this long.
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    pub fn from_device(i2c: I2C, config: Config) -> Self {
+        Self {
+            i2c,
+            address: config.address,
+            variant: config.variant,
+            gain: config.gain,
+        }
+    }
+
+    /// Get a single raw measurement from the ADC. Always signed.
+    pub fn get_raw(&mut self, input: Input) -> io::Result<i16> {
+        let (mux, sign) = Self::get_mux(input)?;
+
+        let config: u16 = (0x1 << 15) // Start a single conversion
+            | ((mux as u16) << 12)
+            | ((self.gain as u16) << 9)
+            | (0x1 << 8) // Single-shot mode
+            | (0b111 << 5) // Fastest data rate
+            | 0b11; // Comparator off
+        let [high, low] = config.to_be_bytes();
+        self.write(&[Self::CONFIG, high, low])?;
+
+        // The start bit reads back as 1 once the conversion is done.
+        let start = Instant::now();
+        while self.read(Self::CONFIG)?[0] & 0x80 == 0 {
+            if start.elapsed() > Self::TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "ADS1x15 conversion did not finish",
+                ));
+            }
+            thread::sleep(Duration::from_micros(200));
+        }
+
+        let raw = self.variant.parse(self.read(Self::CONVERSION)?);
+        Ok(raw.saturating_mul(sign))
+    }
+
+    /// The multiplexer setting for an input, and the sign to apply to the result.
+    /// Only CH0-CH1 and CH2-CH3 are native pairs here, so their reverses are negated.
+    fn get_mux(input: Input) -> io::Result<(u8, i16)> {
+        Ok(match input {
+            Input::Single(
+                channel @ (Channel::CH0 | Channel::CH1 | Channel::CH2 | Channel::CH3),
+            ) => (0b100 | channel as u8, 1),
+            Input::Differential(Pair::CH0_CH1) => (0b000, 1),
+            Input::Differential(Pair::CH1_CH0) => (0b000, -1),
+            Input::Differential(Pair::CH2_CH3) => (0b011, 1),
+            Input::Differential(Pair::CH3_CH2) => (0b011, -1),
+            _ => return Err(unsupported(input)),
+        })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.i2c
+            .write(self.address, bytes)
+            .map_err(|e| io::Error::other(format!("I2C write failed: {:?}", e)))
+    }
+
+    fn read(&mut self, register: u8) -> io::Result<[u8; 2]> {
+        let mut buf = [0; 2];
+        self.i2c
+            .write_read(self.address, &[register], &mut buf)
+            .map_err(|e| io::Error::other(format!("I2C read failed: {:?}", e)))?;
+        Ok(buf)
+    }
+}
+
+impl<I2C: I2c> Adc for ADS1x15<I2C> {
+    fn vref(&self) -> f64 {
+        self.gain.full_scale()
+    }
+
+    fn get_single(&mut self, input: Input) -> io::Result<f64> {
+        Ok(self.get_raw(input)? as f64 / self.variant.max_raw())
+    }
+}
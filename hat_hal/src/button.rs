@@ -0,0 +1,370 @@
+/**
+ * Hardware interface for a button. With debounce, repeat events and gestures.
+ *
+ * The line is either polled on each update, or read from edge events so presses between updates aren't lost.
+ */
+use std::{
+    io,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::edges::{self, Edge};
+
+/// Something the button did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Pressed down, or held past the repeat timeout.
+    Pressed,
+    /// Still held, after each repeat timeout.
+    Repeat,
+    /// Let go, after being held this long.
+    Released(Duration),
+    /// A short press with no second press after it. Sent once the double click time has passed.
+    Click,
+    /// A second press soon after a short press.
+    DoubleClick,
+    /// Held past the long press time. Sent once per press, while still held.
+    LongPress,
+}
+
+/// Times for telling gestures apart.
+#[derive(Debug, Clone, Copy)]
+pub struct Gestures {
+    /// Held this long is a long press, rather than a click.
+    pub long_press: Duration,
+    /// A second press within this long of a release is a double click.
+    pub double_click: Duration,
+}
+
+impl Default for Gestures {
+    fn default() -> Self {
+        Self {
+            long_press: Duration::from_millis(600),
+            double_click: Duration::from_millis(300),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+enum State {
+    Pressed(Instant),
+    Repeat(Instant),
+    #[default]
+    Up,
+}
+
+/// The press in progress.
+#[derive(Debug, Clone, Copy)]
+struct Press {
+    start: Instant,
+    /// The long press was sent.
+    long: bool,
+    /// Can still become a click or long press. Not after a double click, or in a chord.
+    gesture: bool,
+}
+
+/// Where the level of the button comes from.
+enum Source {
+    /// Read the line on each update.
+    Polled(gpiod::Lines<gpiod::Input>),
+    /// Replay the edges since the last update, at their kernel timestamps.
+    Edges {
+        edges: mpsc::Receiver<Edge>,
+        handle: Option<thread::JoinHandle<io::Result<()>>>,
+        level: bool,
+    },
+}
+
+/// Debounced button, updated by the caller.
+pub struct Button {
+    debounce: Duration,
+    timeout: Duration,
+    repeat_timeout: Duration,
+    gestures: Gestures,
+
+    source: Source,
+
+    state: State,
+    /// Time of the last update, so late edges are never handled before it.
+    last: Option<Instant>,
+    /// When the button was last let go, to ignore bounces after a release.
+    released: Option<Instant>,
+    press: Option<Press>,
+    /// Release time of a short press, that may still become a double click.
+    click: Option<Instant>,
+}
+
+impl Button {
+    /// Create a button that is polled on each update. Held longer than `timeout`, it repeats every `repeat_timeout`.
+    pub fn new(
+        pin: gpiod::Lines<gpiod::Input>,
+        debounce: Duration,
+        timeout: Duration,
+        repeat_timeout: Duration,
+    ) -> io::Result<Self> {
+        Ok(Self::from_source(
+            Source::Polled(pin),
+            debounce,
+            timeout,
+            repeat_timeout,
+        ))
+    }
+
+    /// Create a button from edge events. The line must be requested with `.edge(gpiod::EdgeDetect::Both)`.
+    ///
+    /// Every press is seen, even when updates are far apart. gpiod 0.3 can't set the kernel's debounce
+    /// period, so bounces are removed here, using the kernel timestamps.
+    pub fn with_edges(
+        pin: gpiod::Lines<gpiod::Input>,
+        debounce: Duration,
+        timeout: Duration,
+        repeat_timeout: Duration,
+    ) -> io::Result<Self> {
+        let [level] = pin.get_values([false])?;
+        let (handle, edges) = edges::make_edge_thread(pin);
+        let source = Source::Edges {
+            edges,
+            handle: Some(handle),
+            level,
+        };
+
+        Ok(Self::from_source(source, debounce, timeout, repeat_timeout))
+    }
+
+    fn from_source(
+        source: Source,
+        debounce: Duration,
+        timeout: Duration,
+        repeat_timeout: Duration,
+    ) -> Self {
+        Self {
+            debounce,
+            timeout,
+            repeat_timeout,
+            gestures: Gestures::default(),
+            source,
+            state: State::default(),
+            last: None,
+            released: None,
+            press: None,
+            click: None,
+        }
+    }
+
+    /// Set the times for long presses and double clicks.
+    pub fn set_gestures(&mut self, gestures: Gestures) {
+        self.gestures = gestures;
+    }
+
+    /// Get the events since the last update. Fails if the line can't be read, or once if the edge thread has stopped.
+    pub fn update(&mut self, now: Instant) -> io::Result<Vec<Event>> {
+        let mut events = Vec::new();
+
+        let pressed = match &mut self.source {
+            Source::Polled(pin) => pin.get_values([false])?[0],
+            Source::Edges {
+                edges,
+                handle,
+                level,
+            } => {
+                let queued: Vec<_> = edges.try_iter().collect();
+                if queued.is_empty()
+                    && let Err(mpsc::TryRecvError::Disconnected) = edges.try_recv()
+                    && let Some(handle) = handle.take()
+                {
+                    handle.join().map_err(|e| {
+                        io::Error::other(format!("Button edge thread panicked: {e:?}"))
+                    })??;
+                }
+                if let Some(edge) = queued.last() {
+                    *level = edge.level;
+                }
+                let level = *level;
+
+                for edge in queued {
+                    events.extend(self.update_state(edge.level, edge.time));
+                }
+                level
+            }
+        };
+
+        events.extend(self.update_state(pressed, now));
+        Ok(events)
+    }
+
+    /// Whether the button is down, after debouncing.
+    pub fn is_pressed(&self) -> bool {
+        self.press.is_some()
+    }
+
+    /// Stop the current press from becoming a click or long press, like when it is part of a chord.
+    pub fn cancel_gestures(&mut self) {
+        if let Some(press) = &mut self.press {
+            press.gesture = false;
+        }
+        self.click = None;
+    }
+
+    fn update_state(&mut self, pressed: bool, now: Instant) -> Vec<Event> {
+        let now = self.last.map_or(now, |last| now.max(last));
+        self.last = Some(now);
+
+        let (next, event) = match self.state {
+            State::Up => {
+                let settled = self
+                    .released
+                    .is_none_or(|released| now - released >= self.debounce);
+                if pressed && settled {
+                    (State::Pressed(now), Some(Event::Pressed))
+                } else {
+                    (State::Up, None)
+                }
+            }
+            State::Pressed(prev) => {
+                let delta = now - prev;
+                if pressed || delta < self.debounce {
+                    if delta > self.timeout {
+                        (State::Repeat(now), Some(Event::Pressed))
+                    } else {
+                        (State::Pressed(prev), None)
+                    }
+                } else {
+                    (State::Up, None)
+                }
+            }
+            State::Repeat(prev) => {
+                let delta = now - prev;
+                if pressed || delta < self.debounce {
+                    if delta > self.repeat_timeout {
+                        (State::Repeat(now), Some(Event::Repeat))
+                    } else {
+                        (State::Repeat(prev), None)
+                    }
+                } else {
+                    (State::Up, None)
+                }
+            }
+        };
+
+        let was_up = matches!(self.state, State::Up);
+        let is_up = matches!(next, State::Up);
+        self.state = next;
+        if !was_up && is_up {
+            self.released = Some(now);
+        }
+
+        let mut events = Vec::from_iter(event);
+        events.extend(self.update_gestures(was_up, is_up, now));
+        events
+    }
+
+    /// Find gestures from the debounced presses and releases.
+    fn update_gestures(&mut self, was_up: bool, is_up: bool, now: Instant) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        // No second press came in time.
+        if self
+            .click
+            .is_some_and(|release| now - release > self.gestures.double_click)
+        {
+            self.click = None;
+            events.push(Event::Click);
+        }
+
+        if was_up && !is_up {
+            let double = self.click.take().is_some();
+            if double {
+                events.push(Event::DoubleClick);
+            }
+            self.press = Some(Press {
+                start: now,
+                long: false,
+                gesture: !double,
+            });
+        } else if !was_up
+            && is_up
+            && let Some(press) = self.press.take()
+        {
+            events.push(Event::Released(now - press.start));
+            if press.gesture && !press.long {
+                self.click = Some(now);
+            }
+        }
+
+        if let Some(press) = &mut self.press
+            && press.gesture
+            && !press.long
+            && now - press.start >= self.gestures.long_press
+        {
+            press.long = true;
+            events.push(Event::LongPress);
+        }
+
+        events
+    }
+}
+
+/// Something one button, or several held together, did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupEvent {
+    /// An event from the button at this index.
+    Button(usize, Event),
+    /// Two or more buttons held together. Bit `i` is set for the button at index `i`.
+    Chord(u32),
+}
+
+/// Buttons that are read together, so they can be held as chords.
+/// Buttons in a chord don't also send clicks or long presses.
+pub struct ButtonGroup {
+    buttons: Vec<Button>,
+    /// Buttons held down, as bits.
+    held: u32,
+    /// The chord that was last sent, until every button is let go.
+    chord: u32,
+}
+
+impl ButtonGroup {
+    /// Group up to 32 buttons.
+    pub fn new(buttons: Vec<Button>) -> Self {
+        assert!(
+            buttons.len() <= 32,
+            "A button group must have at most 32 buttons."
+        );
+        Self {
+            buttons,
+            held: 0,
+            chord: 0,
+        }
+    }
+
+    /// Get the events since the last update. Fails if any button does.
+    pub fn update(&mut self, now: Instant) -> io::Result<Vec<GroupEvent>> {
+        let mut events = Vec::new();
+        for (index, button) in self.buttons.iter_mut().enumerate() {
+            for event in button.update(now)? {
+                events.push(GroupEvent::Button(index, event));
+            }
+            if button.is_pressed() {
+                self.held |= 1 << index;
+            } else {
+                self.held &= !(1 << index);
+            }
+        }
+
+        if self.held == 0 {
+            self.chord = 0;
+        } else if self.held.count_ones() >= 2 && self.held & !self.chord != 0 {
+            self.chord |= self.held;
+            for (index, button) in self.buttons.iter_mut().enumerate() {
+                if self.chord & (1 << index) != 0 {
+                    button.cancel_gestures();
+                }
+            }
+            events.push(GroupEvent::Chord(self.held));
+        }
+
+        Ok(events)
+    }
+}
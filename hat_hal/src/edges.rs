@@ -0,0 +1,87 @@
+/**
+ * Edge events from GPIO input lines, read on a thread that sleeps in the kernel until a line changes.
+ *
+ * The kernel timestamps each edge as it happens, so events keep their real timing however late they are read.
+ */
+use std::{
+    io,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A change on one of the requested lines.
+#[derive(Debug, Clone, Copy)]
+pub struct Edge {
+    /// Index of the line, in the order it was requested.
+    pub line: usize,
+    /// Level of the line after the edge, with its active setting applied.
+    pub level: bool,
+    pub time: Instant,
+}
+
+/// Converts kernel event timestamps, which count from boot on the monotonic clock, to `Instant`s.
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    instant: Instant,
+    monotonic: Duration,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self {
+            instant: Instant::now(),
+            monotonic: monotonic_now(),
+        }
+    }
+
+    pub fn to_instant(&self, timestamp: Duration) -> Instant {
+        match timestamp.checked_sub(self.monotonic) {
+            Some(after) => self.instant + after,
+            None => self.instant - (self.monotonic - timestamp),
+        }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn monotonic_now() -> Duration {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `now` is a valid timespec to write into, and CLOCK_MONOTONIC always exists on Linux.
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
+
+/// Create the thread that reads edges from `lines`. They must be requested with edge detection.
+///
+/// The thread blocks in the kernel between edges, so it can't be woken to stop.
+/// It ends at the first edge after the receiver is dropped.
+pub fn make_edge_thread(
+    mut lines: gpiod::Lines<gpiod::Input>,
+) -> (thread::JoinHandle<io::Result<()>>, mpsc::Receiver<Edge>) {
+    let (data, edge_rx) = mpsc::channel();
+
+    let handle = thread::spawn::<_, io::Result<()>>(move || {
+        let clock = Clock::new();
+        loop {
+            let event = lines.read_event()?;
+            let edge = Edge {
+                line: event.line as usize,
+                level: matches!(event.edge, gpiod::Edge::Rising),
+                time: clock.to_instant(event.time),
+            };
+            if data.send(edge).is_err() {
+                break Ok(());
+            }
+        }
+    });
+
+    (handle, edge_rx)
+}
@@ -0,0 +1,272 @@
+/**
+ * Hardware interface for a quadrature encoder.
+ *
+ * Edges are read from the kernel on their own thread, so no step is missed however rarely the encoder is updated.
+ */
+use std::{
+    collections::VecDeque,
+    io,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::edges::{self, Edge};
+
+/// How the encoder moved between two consecutive states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transition {
+    Still,
+    Cw,
+    Ccw,
+    /// Both lines changed at once, so an edge was missed and the direction is unknown.
+    Invalid,
+}
+
+impl Transition {
+    /// Every transition between the states (A, B) = 0b00, 0b01, 0b10, 0b11, indexed by [from][to].
+    const TABLE: [[Transition; 4]; 4] = {
+        use Transition::*;
+        [
+            [Still, Ccw, Cw, Invalid],
+            [Cw, Still, Invalid, Ccw],
+            [Ccw, Invalid, Still, Cw],
+            [Invalid, Cw, Ccw, Still],
+        ]
+    };
+
+    fn new(from: (bool, bool), to: (bool, bool)) -> Self {
+        let index = |(a, b): (bool, bool)| ((a as usize) << 1) | b as usize;
+        Self::TABLE[index(from)][index(to)]
+    }
+
+    fn delta(self) -> i32 {
+        match self {
+            Self::Cw => -1,
+            Self::Ccw => 1,
+            Self::Still | Self::Invalid => 0,
+        }
+    }
+}
+
+/// Turns the levels of the A and B lines into detent steps.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    state: (bool, bool),
+    /// Quarter steps since the last detent.
+    counts: i32,
+    counts_per_detent: i32,
+    errors: u64,
+}
+
+impl Decoder {
+    /// Start from the current levels. Most encoders have 4 counts per detent, some have 2 or 1.
+    pub fn new(state: (bool, bool), counts_per_detent: i32) -> Self {
+        Self {
+            state,
+            counts: 0,
+            counts_per_detent: counts_per_detent.max(1),
+            errors: 0,
+        }
+    }
+
+    /// Move to the new levels. Returns a step of +1 or -1 when that finishes a detent.
+    pub fn update(&mut self, state: (bool, bool)) -> Option<i32> {
+        let transition = Transition::new(self.state, state);
+        self.state = state;
+        if transition == Transition::Invalid {
+            self.errors += 1;
+        }
+
+        self.counts += transition.delta();
+        if self.counts.abs() >= self.counts_per_detent {
+            let step = self.counts.signum();
+            self.counts = 0;
+            Some(step)
+        } else {
+            None
+        }
+    }
+
+    /// Transitions where both lines changed at once. Each one is a missed edge.
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+}
+
+/// How far each detent moves, by how fast the encoder is turning.
+/// Slow turns move 1 per detent, for fine adjustment. Fast spins take larger steps, to cover a range quickly.
+#[derive(Debug, Clone, Copy)]
+pub struct Acceleration {
+    /// Speed in detents per second, up to which each detent moves by 1.
+    pub threshold: f64,
+    /// Added to the step, per detent per second above the threshold, raised to the `exponent`.
+    pub gain: f64,
+    pub exponent: f64,
+    /// Largest step for one detent.
+    pub max: f64,
+}
+
+impl Acceleration {
+    /// Every detent moves by 1.
+    pub const NONE: Self = Self {
+        threshold: f64::INFINITY,
+        gain: 0.0,
+        exponent: 1.0,
+        max: 1.0,
+    };
+
+    /// The step for one detent, turned at `speed` detents per second.
+    pub fn step(&self, speed: f64) -> f64 {
+        let excess = (speed.abs() - self.threshold).max(0.0);
+        (1.0 + self.gain * excess.powf(self.exponent)).min(self.max.max(1.0))
+    }
+}
+
+impl Default for Acceleration {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
+/// Quadrature encoder, read from edge events. The caller collects the steps with `update`.
+pub struct Encoder {
+    decoder: Decoder,
+    levels: [bool; 2],
+
+    edges: mpsc::Receiver<Edge>,
+    handle: Option<thread::JoinHandle<io::Result<()>>>,
+
+    position: i64,
+    acc: f64,
+    acceleration: Acceleration,
+    /// Recent steps, for the velocity.
+    recent: VecDeque<(Instant, i32)>,
+}
+
+impl Encoder {
+    /// Steps within this long are used for the velocity.
+    const VELOCITY_WINDOW: Duration = Duration::from_millis(100);
+
+    /// Create an encoder from its A and B lines, with 4 counts per detent.
+    /// They must be requested with `.edge(gpiod::EdgeDetect::Both)`.
+    pub fn new(pins: gpiod::Lines<gpiod::Input>) -> io::Result<Self> {
+        Self::with_detent(pins, 4)
+    }
+
+    /// Create an encoder from its A and B lines, which must be requested with both edges detected.
+    pub fn with_detent(
+        pins: gpiod::Lines<gpiod::Input>,
+        counts_per_detent: i32,
+    ) -> io::Result<Self> {
+        let levels = pins.get_values([false, false])?;
+        let (handle, edges) = edges::make_edge_thread(pins);
+
+        Ok(Self {
+            decoder: Decoder::new((levels[0], levels[1]), counts_per_detent),
+            levels,
+            edges,
+            handle: Some(handle),
+            position: 0,
+            acc: 0.0,
+            acceleration: Acceleration::NONE,
+            recent: VecDeque::new(),
+        })
+    }
+
+    /// Set how steps grow when the encoder is turned quickly.
+    pub fn set_acceleration(&mut self, acceleration: Acceleration) {
+        self.acceleration = acceleration;
+    }
+
+    /// Decode every edge since the last update. Fails once, if the edge thread has stopped.
+    pub fn update(&mut self) -> io::Result<()> {
+        loop {
+            let edge = match self.edges.try_recv() {
+                Ok(edge) => edge,
+                Err(mpsc::TryRecvError::Empty) => break Ok(()),
+                Err(mpsc::TryRecvError::Disconnected) => break self.join(),
+            };
+
+            self.levels[edge.line.min(1)] = edge.level;
+            if let Some(step) = self.decoder.update((self.levels[0], self.levels[1])) {
+                // Speed from the time since the last detent, so a single fast flick is already accelerated.
+                let speed = match self.recent.back() {
+                    Some(&(time, last)) if last == step && edge.time > time => {
+                        1.0 / (edge.time - time).as_secs_f64()
+                    }
+                    _ => 0.0,
+                };
+
+                self.position += step as i64;
+                self.acc += step as f64 * self.acceleration.step(speed);
+                self.recent.push_back((edge.time, step));
+                self.forget(edge.time);
+            }
+        }
+    }
+
+    /// Get the total accumulated delta since the last call, with acceleration applied.
+    pub fn get_delta(&mut self) -> f64 {
+        std::mem::take(&mut self.acc)
+    }
+
+    /// Get the whole part of the accumulated delta, keeping the rest for later calls.
+    pub fn get_acc_delta(&mut self) -> i32 {
+        let delta = self.acc.trunc();
+        self.acc -= delta;
+        delta as i32
+    }
+
+    /// Detents turned since the encoder was created.
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    /// Detents per second, over the last `VELOCITY_WINDOW`.
+    pub fn velocity(&mut self, now: Instant) -> f64 {
+        self.forget(now);
+        let steps: i32 = self.recent.iter().map(|(_, step)| step).sum();
+        steps as f64 / Self::VELOCITY_WINDOW.as_secs_f64()
+    }
+
+    /// Drop steps older than the velocity window.
+    fn forget(&mut self, now: Instant) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|(time, _)| now.saturating_duration_since(*time) > Self::VELOCITY_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Edges that were missed, found as invalid transitions.
+    pub fn errors(&self) -> u64 {
+        self.decoder.errors()
+    }
+
+    /// Stop listening for edges. Only fails if the edge thread already had.
+    pub fn end(mut self) -> io::Result<()> {
+        let handle = self.handle.take();
+        // The thread exits at the next edge, once it can't send.
+        drop(self);
+        match handle {
+            Some(handle) if handle.is_finished() => Self::join_handle(handle),
+            _ => Ok(()),
+        }
+    }
+
+    fn join(&mut self) -> io::Result<()> {
+        match self.handle.take() {
+            Some(handle) => Self::join_handle(handle),
+            None => Ok(()),
+        }
+    }
+
+    fn join_handle(handle: thread::JoinHandle<io::Result<()>>) -> io::Result<()> {
+        handle
+            .join()
+            .map_err(|e| io::Error::other(format!("Encoder thread panicked: {e:?}")))?
+    }
+}
@@ -0,0 +1,9 @@
+/**
+ * Drivers for the board's ADCs, rotary encoder and button, shared by the assignments.
+ */
+pub mod adc;
+pub mod ads1x15;
+pub mod button;
+pub mod edges;
+pub mod encoder;
+pub mod mcp3x0x;
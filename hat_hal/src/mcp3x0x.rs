@@ -0,0 +1,225 @@
+/**
+ * Hardware interface for the MCP3004/3008 and MCP3204/3208 SPI ADCs.
+ *
+ * Both families use the same 3 byte frame, differing only in where the bits sit and the resolution.
+ * Works with any embedded-hal `SpiDevice` that implements `Transfers`. On Linux, `Mcp3x0x::new` opens a spidev device.
+ */
+use std::io;
+
+use embedded_hal::spi::SpiDevice;
+use linux_embedded_hal::{
+    SpidevDevice,
+    spidev::{SpiModeFlags, SpidevOptions, SpidevTransfer},
+};
+
+use crate::adc::{Adc, Input, median};
+
+/// Which chip is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Family {
+    /// MCP3004/MCP3008, 10-bit.
+    MCP300X,
+    /// MCP3204/MCP3208, 12-bit.
+    MCP320X,
+}
+
+impl Family {
+    /// Readings per full scale.
+    pub fn max_raw(self) -> u16 {
+        match self {
+            Family::MCP300X => 1024,
+            Family::MCP320X => 4096,
+        }
+    }
+
+    /// Get the packet to transmit. `code` is the channel when single-ended, or the pair when differential.
+    fn get_tx(self, single: bool, code: u8) -> [u8; 3] {
+        let single = single as u8;
+        match self {
+            Family::MCP300X => [
+                0x1,                         // [0b0000_000(start)]
+                (single << 7) | (code << 4), // [0b(single)(d2)(d1)(d0)_xxxx]
+                0x0,                         // [0bxxxx_xxxx]
+            ],
+            Family::MCP320X => [
+                (0x1 << 2) | (single << 1) | (code >> 2), // [0b0000_0(start)(single)(d2)]
+                (code << 6),                              // [0b(D1)(D0)xx_xxxx]
+                0x0,                                      // [0bxxxx_xxxx]
+            ],
+        }
+    }
+
+    /// Parse the received packet for the raw measurement
+    fn parse_rx(self, rx: [u8; 3]) -> u16 {
+        match self {
+            // rx = { [0bZZZZ_ZZZZ], [0bZZZZ_Z(null)(B9)(B8)], [0b(B7)(B6)(B5)(B4)_(B3)(B2)(B1)(B0)] }
+            Family::MCP300X => (((rx[1] & 0x03) as u16) << 8) | rx[2] as u16,
+            // rx = { [0bZZZZ_ZZZZ], [0bZZZ(null)_(B11)(B10)(B9)(B8)], [0b(B7)(B6)(B5)(B4)_(B3)(B2)(B1)(B0)] }
+            Family::MCP320X => (((rx[1] & 0x0F) as u16) << 8) | rx[2] as u16,
+        }
+    }
+}
+
+/// Settings for the ADC and its SPI bus.
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    /// Assumed reference voltage.
+    pub vref: f64,
+    pub max_speed_hz: u32,
+    pub family: Family,
+}
+
+impl Config {
+    pub fn new(family: Family, vref: f64) -> Self {
+        Self {
+            vref,
+            max_speed_hz: 500000,
+            family,
+        }
+    }
+}
+
+/// Sends a batch of conversions, with chip select toggled between each, since each conversion starts on its falling edge.
+///
+/// Spidev does the whole batch in one ioctl. Other devices can use the default of one transaction each,
+/// with an empty `impl Transfers for Device {}`.
+pub trait Transfers: SpiDevice {
+    fn transfer_each(&mut self, tx: &[[u8; 3]], rx: &mut [[u8; 3]]) -> io::Result<()> {
+        for (tx, rx) in tx.iter().zip(rx.iter_mut()) {
+            self.transfer(rx, tx)
+                .map_err(|e| io::Error::other(format!("SPI transfer failed: {:?}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl Transfers for SpidevDevice {
+    fn transfer_each(&mut self, tx: &[[u8; 3]], rx: &mut [[u8; 3]]) -> io::Result<()> {
+        /// Conversions per ioctl, keeping well inside spidev's 4 KiB buffer.
+        const MAX_TRANSFERS: usize = 256;
+
+        for (tx, rx) in tx.chunks(MAX_TRANSFERS).zip(rx.chunks_mut(MAX_TRANSFERS)) {
+            let mut transfers: Vec<_> = tx
+                .iter()
+                .zip(rx.iter_mut())
+                .map(|(tx, rx)| {
+                    let mut transfer = SpidevTransfer::read_write(tx, rx);
+                    transfer.cs_change = 1;
+                    transfer
+                })
+                .collect();
+            // On the last transfer, cs_change would instead leave it selected afterwards.
+            if let Some(last) = transfers.last_mut() {
+                last.cs_change = 0;
+            }
+            self.transfer_multiple(&mut transfers)?;
+        }
+        Ok(())
+    }
+}
+
+/// MCP300X or MCP320X connected over SPI, with an assumed Vref.
+pub struct Mcp3x0x<SPI = SpidevDevice> {
+    spi: SPI,
+    vref: f64,
+    family: Family,
+}
+
+impl Mcp3x0x {
+    /// Open a connection with an MCP300X or MCP320X over spidev, at the default speed.
+    pub fn new<P: AsRef<std::path::Path>>(path: P, family: Family, vref: f64) -> io::Result<Self> {
+        Self::open(path, Config::new(family, vref))
+    }
+
+    /// Open a connection with an MCP300X or MCP320X over spidev.
+    pub fn open<P: AsRef<std::path::Path>>(path: P, config: Config) -> io::Result<Self> {
+        let mut spi = SpidevDevice::open(path).map_err(io::Error::other)?;
+        spi.configure(&SpidevOptions {
+            spi_mode: Some(SpiModeFlags::SPI_MODE_0),
+            bits_per_word: Some(8),
+            max_speed_hz: Some(config.max_speed_hz),
+            lsb_first: Some(false),
+        })?;
+
+        Ok(Self::from_device(spi, config))
+    }
+}
+
+impl<SPI: Transfers> Mcp3x0x<SPI> {
+    /// Use an already configured SPI device. It must be in SPI mode 0, at no more than 1 MHz.
+    pub fn from_device(spi: SPI, config: Config) -> Self {
+        Self {
+            spi,
+            vref: config.vref,
+            family: config.family,
+        }
+    }
+
+    /// Get a single raw measurement from the ADC. Differential inputs are signed.
+    pub fn get_raw(&mut self, input: Input) -> io::Result<i32> {
+        let raw = self.convert(frames(input))?;
+        Ok(combine(input, raw.into_iter()))
+    }
+
+    /// Do the conversions, given as (single-ended, code).
+    fn convert(&mut self, frames: impl Iterator<Item = (bool, u8)>) -> io::Result<Vec<u16>> {
+        let tx: Vec<_> = frames
+            .map(|(single, code)| self.family.get_tx(single, code))
+            .collect();
+        let mut rx = vec![[0u8; 3]; tx.len()];
+        self.spi.transfer_each(&tx, &mut rx)?;
+        Ok(rx.into_iter().map(|rx| self.family.parse_rx(rx)).collect())
+    }
+}
+
+impl<SPI: Transfers> Adc for Mcp3x0x<SPI> {
+    fn vref(&self) -> f64 {
+        self.vref
+    }
+
+    fn get_single(&mut self, input: Input) -> io::Result<f64> {
+        Ok(self.get_raw(input)? as f64 / self.family.max_raw() as f64)
+    }
+
+    /// All the conversions go to the device together, so the inputs are read close together in time.
+    /// With spidev, that is also a single syscall.
+    fn read_many(&mut self, inputs: &[Input], sample_count: usize) -> io::Result<Vec<f64>> {
+        // Interleave the inputs, so each round of samples is as close together as possible.
+        let raw = self.convert(
+            (0..sample_count).flat_map(|_| inputs.iter().flat_map(|&input| frames(input))),
+        )?;
+
+        let max_raw = self.family.max_raw() as f64;
+        let mut raw = raw.into_iter();
+        let mut samples = vec![Vec::with_capacity(sample_count); inputs.len()];
+        for _ in 0..sample_count {
+            for (&input, samples) in inputs.iter().zip(samples.iter_mut()) {
+                let count = frames(input).count();
+                samples.push(combine(input, raw.by_ref().take(count)) as f64 / max_raw);
+            }
+        }
+
+        samples.iter_mut().map(|samples| median(samples)).collect()
+    }
+}
+
+/// The conversions needed for an input, as (single-ended, channel or pair code).
+///
+/// The chip is only pseudo-differential, reading 0 whenever the negative side is higher.
+/// So both directions of a pair are read, for a signed result.
+fn frames(input: Input) -> impl Iterator<Item = (bool, u8)> {
+    let (first, second) = match input {
+        Input::Single(channel) => ((true, channel as u8), None),
+        Input::Differential(pair) => ((false, pair as u8), Some((false, pair.reversed() as u8))),
+    };
+    std::iter::once(first).chain(second)
+}
+
+/// Combine the raw results of `frames`.
+fn combine(input: Input, mut raw: impl Iterator<Item = u16>) -> i32 {
+    let first = raw.next().unwrap_or_default() as i32;
+    match input {
+        Input::Single(_) => first,
+        Input::Differential(_) => first - raw.next().unwrap_or_default() as i32,
+    }
+}